@@ -1,140 +1,1085 @@
+use chrono::{DateTime, TimeDelta, Utc};
+use derive_more::Display;
 use lru::LruCache;
 use parking_lot::RwLock;
-use std::{collections::HashMap, num::NonZero, sync::LazyLock};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    num::NonZero,
+    ops::RangeInclusive,
+    sync::{Arc, LazyLock},
+};
 
 use crate::{
-    invariants::{Endpoint, Hostname, Timestamp},
+    capped::{Capped, Weighted},
+    histogram::Histogram,
+    hyperloglog::HyperLogLog,
+    invariants::{
+        AppName, Endpoint, ExtraValue, Hostname, Level, Message, Referrer, Service, Timestamp,
+    },
+    models::Severity,
+    parser::{LogFormat, ParseError},
     prometheus::PromMetrics,
+    space_saving::SpaceSaving,
+    timeseries::{self, Resolution, TimeSeries},
 };
 
-static MAX_HOURS: LazyLock<NonZero<usize>> =
-    LazyLock::new(|| NonZero::new(6).expect("nonzero const"));
-static MAX_PATHS: LazyLock<NonZero<usize>> =
-    LazyLock::new(|| NonZero::new(10).expect("nonzero const"));
+/// Hours kept by hourly dimensions while serving metrics.
+const MAX_HOURS: usize = 6;
+/// Counters kept for paths, top paths are off by at most `hits / PATH_COUNTERS`.
+const PATH_COUNTERS: usize = 1_000;
+/// Counters kept for referrers, see [`PATH_COUNTERS`].
+const REFERRER_COUNTERS: usize = 100;
+const MAX_BAD_LINES: usize = 100;
+const MAX_BAD_LINE_LEN: usize = 1024;
+/// Counters kept for messages, see [`PATH_COUNTERS`].
+const MESSAGE_COUNTERS: usize = 100;
+/// Counters kept for syslog app names, see [`PATH_COUNTERS`].
+const SYSLOG_APP_COUNTERS: usize = 100;
+static MAX_SKEWED_HOSTS: LazyLock<NonZero<usize>> =
+    LazyLock::new(|| NonZero::new(50).expect("nonzero const"));
+
+/// Label keys folded out of a capped dimension are reported under.
+const OTHER: &str = "other";
+/// Appended to a source's name for where `--late-events route` sends events.
+const LATE_SUFFIX: &str = ".late";
+
+/// Status codes counted per code, anything else only under `other`.
+const STATUS_CODES: RangeInclusive<u16> = 100..=599;
+
+/// The hundreds digit of a status code, shown as `2xx`, `4xx` and so on.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[display("{_0}xx")]
+pub struct StatusClass(u16);
+
+impl StatusClass {
+    pub fn of(status: u16) -> Self {
+        Self(status / 100)
+    }
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Event {
-    Ok,
-    NotFound,
-    InternalError,
-    Redirect,
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UserAgentFamily {
+    Bot,
+    Curl,
+    Wget,
+    Python,
+    Go,
+    Edge,
+    Opera,
+    Chrome,
+    Firefox,
+    Safari,
+    #[display("Internet Explorer")]
+    InternetExplorer,
+    Other,
 }
 
-impl Event {
-    pub fn try_from_status(status: u16) -> Option<Self> {
-        match status {
-            200 => Some(Self::Ok),
-            301 => Some(Self::Redirect),
-            404 => Some(Self::NotFound),
-            500 => Some(Self::InternalError),
-            _ => None,
+impl UserAgentFamily {
+    /// Buckets a raw `User-Agent` header into a coarse browser/client family.
+    /// Order matters: most browsers claim to be several others at once.
+    pub fn classify(user_agent: &str) -> Self {
+        let ua = user_agent.to_ascii_lowercase();
+        let has = |needle: &str| ua.contains(needle);
+        if has("bot") || has("spider") || has("crawl") || has("slurp") {
+            Self::Bot
+        } else if ua.starts_with("curl/") {
+            Self::Curl
+        } else if ua.starts_with("wget/") {
+            Self::Wget
+        } else if ua.starts_with("python") {
+            Self::Python
+        } else if ua.starts_with("go-http-client") {
+            Self::Go
+        } else if has("edg/") || has("edge/") {
+            Self::Edge
+        } else if has("opr/") || has("opera") {
+            Self::Opera
+        } else if has("chrome/") || has("crios/") || has("chromium/") {
+            Self::Chrome
+        } else if has("firefox/") || has("fxios/") {
+            Self::Firefox
+        } else if has("safari/") {
+            Self::Safari
+        } else if has("msie ") || has("trident/") {
+            Self::InternetExplorer
+        } else {
+            Self::Other
         }
     }
-    pub fn to_status(self) -> u16 {
-        match self {
-            Self::Ok => 200u16,
-            Self::Redirect => 301u16,
-            Self::NotFound => 404u16,
-            Self::InternalError => 500u16,
+}
+
+/// A rejected line kept for troubleshooting, see [`Analytics::recent_bad_lines`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BadLine {
+    pub format: LogFormat,
+    pub reason: ParseError,
+    pub line: String,
+}
+
+/// Memory bounds for each source's unbounded dimensions, and how late an
+/// event may arrive. Keys past a limit are folded into an `other` bucket,
+/// lightest first.
+#[derive(clap::Args, Debug, Clone)]
+pub struct LimitArgs {
+    /// Most status codes counted individually
+    #[arg(long, default_value_t = 100)]
+    pub max_statuses: usize,
+
+    /// Most hosts whose hits are counted individually
+    #[arg(long, default_value_t = 10_000)]
+    pub max_hosts: usize,
+
+    /// Most hosts whose bytes per hour are tracked individually
+    #[arg(long, default_value_t = 1_000)]
+    pub max_byte_hosts: usize,
+
+    /// Most structured log services counted individually
+    #[arg(long, default_value_t = 100)]
+    pub max_services: usize,
+
+    /// Most structured log levels counted individually
+    #[arg(long, default_value_t = 20)]
+    pub max_levels: usize,
+
+    /// Most values of each extra `--patterns` capture counted individually
+    #[arg(long, default_value_t = 100)]
+    pub max_extra_values: usize,
+
+    /// HyperLogLog precision for distinct host estimates, each estimator
+    /// takes `2^precision` bytes and is off by about `1.04 / sqrt(2^precision)`
+    #[arg(long, default_value_t = 12, value_parser = clap::value_parser!(u8).range(
+        HyperLogLog::MIN_PRECISION as i64..=HyperLogLog::MAX_PRECISION as i64
+    ))]
+    pub hll_precision: u8,
+
+    /// Resolutions hits are bucketed at, as `WIDTH:BUCKETS` with a width in
+    /// s, m, h or d, finest first with each width a multiple of the one
+    /// before, e.g. `1m:60,1h:6,1d:7`
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_RESOLUTIONS)]
+    pub resolutions: Vec<Resolution>,
+
+    /// How far behind the latest event time seen an event may be and still
+    /// be bucketed, e.g. `90s`, `15m` or `1h`
+    #[arg(long, default_value = DEFAULT_ALLOWED_LATENESS, value_parser = timeseries::parse_duration)]
+    pub allowed_lateness: TimeDelta,
+
+    /// How far ahead of the wall clock, and of the watermark when ahead of
+    /// the wall clock at all, an event may be before its host is flagged for
+    /// clock skew and the event is kept out of time buckets
+    #[arg(long, default_value = DEFAULT_MAX_FUTURE_SKEW, value_parser = timeseries::parse_duration)]
+    pub max_future_skew: TimeDelta,
+
+    /// Upper bounds, in bytes, of the response size histogram buckets
+    #[arg(long, value_delimiter = ',', default_value = DEFAULT_SIZE_BUCKETS)]
+    pub size_buckets: Vec<u64>,
+
+    /// What happens to events later than `--allowed-lateness`
+    #[arg(long, value_enum, default_value_t = LatePolicy::Count)]
+    pub late_events: LatePolicy,
+}
+
+const DEFAULT_RESOLUTIONS: &str = "1m:60,1h:6,1d:7";
+const DEFAULT_ALLOWED_LATENESS: &str = "1h";
+const DEFAULT_MAX_FUTURE_SKEW: &str = "5m";
+const DEFAULT_SIZE_BUCKETS: &str = "100,1000,10000,100000,1000000,10000000";
+
+/// What to do with an event that arrives after its buckets have closed.
+/// Either way it is kept out of them, other dimensions still count it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LatePolicy {
+    /// Discard it
+    Drop,
+    /// Discard it and count it in `late_events_total`
+    Count,
+    /// Count it and bucket it under the `<source>.late` source instead
+    Route,
+}
+
+impl Default for LimitArgs {
+    fn default() -> Self {
+        Self {
+            max_statuses: 100,
+            max_hosts: 10_000,
+            max_byte_hosts: 1_000,
+            max_services: 100,
+            max_levels: 20,
+            max_extra_values: 100,
+            hll_precision: 12,
+            resolutions: DEFAULT_RESOLUTIONS
+                .split(',')
+                .map(|r| r.parse().expect("valid default resolution"))
+                .collect(),
+            allowed_lateness: timeseries::parse_duration(DEFAULT_ALLOWED_LATENESS)
+                .expect("valid default lateness"),
+            max_future_skew: timeseries::parse_duration(DEFAULT_MAX_FUTURE_SKEW)
+                .expect("valid default skew"),
+            size_buckets: DEFAULT_SIZE_BUCKETS
+                .split(',')
+                .map(|b| b.parse().expect("valid default size bucket"))
+                .collect(),
+            late_events: LatePolicy::Count,
+        }
+    }
+}
+
+/// Separate [`Analytics`] for every log source, see `--source-tokens`.
+#[derive(Debug, Default)]
+pub struct Sources {
+    limits: LimitArgs,
+    /// Set for archives, see [`Self::batch`].
+    batch: bool,
+    analytics: RwLock<HashMap<String, Arc<Analytics>>>,
+}
+
+impl Sources {
+    pub fn new(limits: LimitArgs) -> Self {
+        Self {
+            limits,
+            batch: false,
+            analytics: RwLock::default(),
+        }
+    }
+    /// Sources for reading archives, whose events are bucketed whenever
+    /// they happened, with no watermark or clock skew check, and whose
+    /// hourly dimensions keep every hour.
+    pub fn batch(limits: LimitArgs) -> Self {
+        Self {
+            batch: true,
+            ..Self::new(limits)
+        }
+    }
+    /// The analytics for `source`, created on first use along with
+    /// `<source>.late` when late events are routed there.
+    pub fn get(&self, source: &str) -> Arc<Analytics> {
+        if let Some(analytics) = self.analytics.read().get(source) {
+            return analytics.clone();
+        }
+        let mut sources = self.analytics.write();
+        if let Some(analytics) = sources.get(source) {
+            return analytics.clone();
         }
+        let analytics = if self.batch {
+            Arc::new(Analytics::build(&self.limits, None, usize::MAX))
+        } else {
+            let late = (self.limits.late_events == LatePolicy::Route).then(|| {
+                sources
+                    .entry(format!("{source}{LATE_SUFFIX}"))
+                    .or_insert_with(|| Arc::new(Analytics::build(&self.limits, None, MAX_HOURS)))
+                    .clone()
+            });
+            let event_time = EventTime::new(&self.limits, late);
+            Arc::new(Analytics::build(&self.limits, Some(event_time), MAX_HOURS))
+        };
+        sources.insert(source.to_owned(), analytics.clone());
+        analytics
+    }
+    pub fn all(&self) -> Vec<(String, Arc<Analytics>)> {
+        self.analytics
+            .read()
+            .iter()
+            .map(|(source, analytics)| (source.clone(), analytics.clone()))
+            .collect()
     }
 }
 
 #[derive(Debug)]
 pub struct Analytics {
-    events: RwLock<HashMap<Event, usize>>,
-    paths: RwLock<LruCache<Endpoint, usize>>,
-    hosts: RwLock<HashMap<Hostname, usize>>,
-    by_hour: RwLock<LruCache<Timestamp, usize>>,
-    bytes_by_hour_per_host: RwLock<HashMap<Hostname, LruCache<Timestamp, u64>>>,
+    parsed: RwLock<HashMap<LogFormat, usize>>,
+    parse_errors: RwLock<HashMap<(LogFormat, ParseError), usize>>,
+    bad_lines: RwLock<VecDeque<BadLine>>,
+    redeliveries: RwLock<HashMap<String, usize>>,
+    events: RwLock<Capped<u16, usize>>,
+    status_classes: RwLock<HashMap<StatusClass, usize>>,
+    paths: RwLock<SpaceSaving<Endpoint>>,
+    hosts: RwLock<Capped<Hostname, usize>>,
+    referrers: RwLock<SpaceSaving<Referrer>>,
+    user_agents: RwLock<HashMap<UserAgentFamily, usize>>,
+    services: RwLock<Capped<Service, usize>>,
+    levels: RwLock<Capped<Level, usize>>,
+    /// Values per extra pattern capture, the captures being fixed by
+    /// `--patterns`.
+    extras: RwLock<HashMap<String, Capped<ExtraValue, usize>>>,
+    max_extra_values: usize,
+    syslog_severities: RwLock<HashMap<Severity, usize>>,
+    syslog_apps: RwLock<SpaceSaving<AppName>>,
+    messages: RwLock<SpaceSaving<Message>>,
+    hits: RwLock<TimeSeries<HashMap<StatusClass, usize>>>,
+    bytes_by_hour_per_host: RwLock<Capped<Hostname, HourlyBytes>>,
+    /// How many hours `bytes_by_hour_per_host` and `hosts_by_hour` keep.
+    hours: usize,
+    hll_precision: u8,
+    hosts_by_hour: RwLock<BTreeMap<Timestamp, HyperLogLog>>,
+    /// Only kept for paths monitored by `paths`, so at most
+    /// [`PATH_COUNTERS`] of them.
+    hosts_by_path: RwLock<HashMap<Endpoint, HyperLogLog>>,
+    size_buckets: Arc<[u64]>,
+    sizes: RwLock<Histogram>,
+    sizes_by_class: RwLock<HashMap<StatusClass, Histogram>>,
+    /// Only kept for paths monitored by `paths`, like `hosts_by_path`.
+    sizes_by_path: RwLock<HashMap<Endpoint, Histogram>>,
+    /// Unset for analytics that take events whenever they happened.
+    event_time: Option<EventTime>,
+    /// Hosts with events too far in the future, by how far and when that
+    /// was last seen on the wall clock.
+    skewed_hosts: RwLock<LruCache<Hostname, (TimeDelta, DateTime<Utc>)>>,
 }
 
-impl Default for Analytics {
+/// Tracks the latest event time seen, the watermark, and decides which
+/// events are too far behind it, or too far ahead of it or the wall clock,
+/// to be bucketed.
+#[derive(Debug)]
+struct EventTime {
+    allowed_lateness: TimeDelta,
+    max_future_skew: TimeDelta,
+    policy: LatePolicy,
+    watermark: RwLock<Option<DateTime<Utc>>>,
+    late_events: RwLock<usize>,
+    /// Where late events are bucketed under [`LatePolicy::Route`].
+    late: Option<Arc<Analytics>>,
+}
+
+impl EventTime {
+    fn new(limits: &LimitArgs, late: Option<Arc<Analytics>>) -> Self {
+        Self {
+            allowed_lateness: limits.allowed_lateness,
+            max_future_skew: limits.max_future_skew,
+            policy: limits.late_events,
+            watermark: RwLock::default(),
+            late_events: RwLock::default(),
+            late,
+        }
+    }
+    /// How far ahead `at` is, when that is more than allowed. Only events
+    /// ahead of the wall clock can be skewed, those are then held to the
+    /// watermark as well, so a backlog or a quiet spell never is.
+    fn skew(&self, at: DateTime<Utc>) -> Option<TimeDelta> {
+        let ahead = at - Utc::now();
+        if ahead <= TimeDelta::zero() {
+            return None;
+        }
+        let offset = match *self.watermark.read() {
+            Some(watermark) => ahead.max(at - watermark),
+            None => ahead,
+        };
+        (offset > self.max_future_skew).then_some(offset)
+    }
+    fn is_late(&self, at: DateTime<Utc>) -> bool {
+        self.watermark
+            .read()
+            .is_some_and(|watermark| at < watermark - self.allowed_lateness)
+    }
+    /// Moves the watermark up to `at`, or counts `at` as late. Skewed
+    /// events are ignored, they would make every other event late.
+    fn observe(&self, at: DateTime<Utc>) {
+        if self.skew(at).is_some() {
+            return;
+        }
+        let mut watermark = self.watermark.write();
+        match *watermark {
+            Some(w) if at < w - self.allowed_lateness => {
+                if self.policy != LatePolicy::Drop {
+                    *self.late_events.write() += 1;
+                }
+            }
+            Some(w) if at <= w => {}
+            _ => *watermark = Some(at),
+        }
+    }
+}
+
+/// Every key's count, plus `other` once any key has been folded.
+fn capped_frequency<K: std::hash::Hash + Ord + Clone + ToString>(
+    map: &Capped<K, usize>,
+) -> HashMap<String, usize> {
+    let mut counts: HashMap<_, _> = map.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+    if map.folded() > 0 {
+        *counts.entry(OTHER.to_owned()).or_default() += map.other();
+    }
+    counts
+}
+
+/// Bytes served in each of the last `retention` hours.
+#[derive(Debug)]
+struct HourlyBytes {
+    retention: usize,
+    hours: BTreeMap<Timestamp, u64>,
+}
+
+impl Default for HourlyBytes {
     fn default() -> Self {
         Self {
-            events: RwLock::default(),
-            paths: RwLock::new(LruCache::new(*MAX_PATHS)),
-            hosts: RwLock::default(),
-            by_hour: RwLock::new(LruCache::new(*MAX_HOURS)),
-            bytes_by_hour_per_host: RwLock::default(),
+            retention: MAX_HOURS,
+            hours: BTreeMap::new(),
         }
     }
 }
 
+impl Weighted for HourlyBytes {
+    fn weight(&self) -> u64 {
+        self.hours.values().sum()
+    }
+    fn absorb(&mut self, other: Self) {
+        let retention = self.retention.max(other.retention);
+        for (hour, bytes) in other.hours {
+            self.add(hour, bytes, retention);
+        }
+    }
+}
+
+impl HourlyBytes {
+    fn add(&mut self, hour: Timestamp, bytes: u64, retention: usize) {
+        self.retention = retention;
+        if let Some(total) = timeseries::bucket_mut(&mut self.hours, hour, retention, || 0) {
+            *total += bytes;
+        }
+    }
+    fn sorted(&self) -> Vec<(Timestamp, u64)> {
+        self.hours.iter().map(|(t, b)| (*t, *b)).collect()
+    }
+}
+
+impl Default for Analytics {
+    fn default() -> Self {
+        Self::new(&LimitArgs::default())
+    }
+}
+
 impl Analytics {
+    /// Analytics with nowhere to route late events to, see [`Sources::get`]
+    /// for those that have.
+    pub fn new(limits: &LimitArgs) -> Self {
+        Self::build(limits, Some(EventTime::new(limits, None)), MAX_HOURS)
+    }
+    fn build(limits: &LimitArgs, event_time: Option<EventTime>, hours: usize) -> Self {
+        let mut size_buckets = limits.size_buckets.clone();
+        size_buckets.sort_unstable();
+        size_buckets.dedup();
+        let size_buckets: Arc<[u64]> = size_buckets.into();
+        Self {
+            parsed: RwLock::default(),
+            parse_errors: RwLock::default(),
+            bad_lines: RwLock::new(VecDeque::with_capacity(MAX_BAD_LINES)),
+            redeliveries: RwLock::default(),
+            events: RwLock::new(Capped::new(limits.max_statuses)),
+            status_classes: RwLock::default(),
+            paths: RwLock::new(SpaceSaving::new(PATH_COUNTERS)),
+            hosts: RwLock::new(Capped::new(limits.max_hosts)),
+            referrers: RwLock::new(SpaceSaving::new(REFERRER_COUNTERS)),
+            user_agents: RwLock::default(),
+            services: RwLock::new(Capped::new(limits.max_services)),
+            levels: RwLock::new(Capped::new(limits.max_levels)),
+            extras: RwLock::default(),
+            max_extra_values: limits.max_extra_values,
+            syslog_severities: RwLock::default(),
+            syslog_apps: RwLock::new(SpaceSaving::new(SYSLOG_APP_COUNTERS)),
+            messages: RwLock::new(SpaceSaving::new(MESSAGE_COUNTERS)),
+            hits: RwLock::new(TimeSeries::new(&limits.resolutions)),
+            bytes_by_hour_per_host: RwLock::new(Capped::new(limits.max_byte_hosts)),
+            hours,
+            hll_precision: limits.hll_precision,
+            hosts_by_hour: RwLock::default(),
+            hosts_by_path: RwLock::default(),
+            sizes: RwLock::new(Histogram::new(size_buckets.clone())),
+            sizes_by_class: RwLock::default(),
+            sizes_by_path: RwLock::default(),
+            size_buckets,
+            event_time,
+            skewed_hosts: RwLock::new(LruCache::new(*MAX_SKEWED_HOSTS)),
+        }
+    }
+
+    /// Where time-bucketed records for an event of `host` at `at` go: here
+    /// while it is on time, nowhere when it is skewed, in which case `host`
+    /// is flagged, otherwise wherever `--late-events` sends it, if anywhere.
+    fn bucketed_by(&self, host: &str, at: DateTime<Utc>) -> Option<&Analytics> {
+        let Some(event_time) = &self.event_time else {
+            return Some(self);
+        };
+        if let Some(offset) = event_time.skew(at) {
+            self.skewed_hosts
+                .write()
+                .put(host.parse().unwrap(), (offset, Utc::now()));
+            return None;
+        }
+        if event_time.is_late(at) {
+            return event_time.late.as_deref();
+        }
+        Some(self)
+    }
+
+    pub fn record_parsed(&self, format: LogFormat) {
+        let mut map = self.parsed.write();
+        *map.entry(format).or_default() += 1;
+    }
+    pub fn record_parse_error(&self, format: LogFormat, reason: ParseError, line: &str) {
+        *self
+            .parse_errors
+            .write()
+            .entry((format, reason))
+            .or_default() += 1;
+        let mut bad_lines = self.bad_lines.write();
+        if bad_lines.len() == MAX_BAD_LINES {
+            bad_lines.pop_front();
+        }
+        bad_lines.push_back(BadLine {
+            format,
+            reason,
+            line: line.chars().take(MAX_BAD_LINE_LEN).collect(),
+        });
+    }
+    pub fn record_redelivery(&self, subject: &str) {
+        let mut map = self.redeliveries.write();
+        *map.entry(subject.to_owned()).or_default() += 1;
+    }
     pub fn record_event(&self, code: u16) {
-        if let Some(e) = Event::try_from_status(code) {
-            let mut map = self.events.write();
-            *map.entry(e).or_default() += 1;
+        if !STATUS_CODES.contains(&code) {
+            self.events.write().update_other(|count| *count += 1);
+            return;
         }
+        self.events.write().update(code, |count| *count += 1);
+        *self
+            .status_classes
+            .write()
+            .entry(StatusClass::of(code))
+            .or_default() += 1;
     }
     pub fn record_path(&self, path: &str) {
-        let mut map = self.paths.write();
-        *map.get_or_insert_mut(path.parse().unwrap(), || 0) += 1;
+        if let Some(evicted) = self.paths.write().insert(path.parse().unwrap()) {
+            self.hosts_by_path.write().remove(&evicted);
+            self.sizes_by_path.write().remove(&evicted);
+        }
+    }
+    /// Adds `bytes` to the overall response size histogram, its status
+    /// class's and, when it is among the monitored paths, its path's.
+    pub fn record_response_size(&self, path: &str, status: u16, bytes: u64) {
+        let new = || Histogram::new(self.size_buckets.clone());
+        self.sizes.write().observe(bytes);
+        self.sizes_by_class
+            .write()
+            .entry(StatusClass::of(status))
+            .or_insert_with(new)
+            .observe(bytes);
+        let path: Endpoint = path.parse().unwrap();
+        if self.paths.read().contains(&path) {
+            self.sizes_by_path
+                .write()
+                .entry(path)
+                .or_insert_with(new)
+                .observe(bytes);
+        }
     }
     pub fn record_host(&self, host: &str) {
-        let mut map = self.hosts.write();
-        *map.entry(host.to_string().parse().unwrap()).or_default() += 1;
+        self.hosts
+            .write()
+            .update(host.parse().unwrap(), |count| *count += 1);
+    }
+    pub fn record_referrer(&self, referrer: &str) {
+        self.referrers.write().insert(referrer.parse().unwrap());
+    }
+    pub fn record_user_agent(&self, user_agent: &str) {
+        let mut map = self.user_agents.write();
+        *map.entry(UserAgentFamily::classify(user_agent))
+            .or_default() += 1;
+    }
+    pub fn record_service(&self, service: &str) {
+        self.services
+            .write()
+            .update(service.parse().unwrap(), |count| *count += 1);
+    }
+    pub fn record_extra(&self, field: &str, value: &str) {
+        let mut extras = self.extras.write();
+        if !extras.contains_key(field) {
+            extras.insert(field.to_owned(), Capped::new(self.max_extra_values));
+        }
+        if let Some(values) = extras.get_mut(field) {
+            values.update(value.parse().unwrap(), |count| *count += 1);
+        }
+    }
+    pub fn record_level(&self, level: &str) {
+        self.levels
+            .write()
+            .update(level.parse().unwrap(), |count| *count += 1);
     }
-    pub fn record_hour_hit(&self, hour: Timestamp) {
-        let mut map = self.by_hour.write();
-        *map.get_or_insert_mut(hour, || 0) += 1;
+    pub fn record_syslog_severity(&self, severity: Severity) {
+        let mut map = self.syslog_severities.write();
+        *map.entry(severity).or_default() += 1;
     }
-    pub fn record_host_hour_bytes(&self, host: &str, hour: Timestamp, bytes: u64) {
-        let mut outer = self.bytes_by_hour_per_host.write();
-        let entry = outer
-            .entry(host.parse().unwrap())
-            .or_insert_with(|| LruCache::new(*MAX_HOURS));
-        *entry.get_or_insert_mut(hour, || 0) += bytes;
+    pub fn record_syslog_app(&self, app_name: &str) {
+        self.syslog_apps.write().insert(app_name.parse().unwrap());
+    }
+    pub fn record_message(&self, message: &str) {
+        self.messages.write().insert(message.parse().unwrap());
+    }
+    /// Records a hit and moves the watermark, every event is expected to
+    /// be recorded as a hit exactly once.
+    pub fn record_hit(&self, host: &str, at: DateTime<Utc>, status: u16) {
+        if let Some(event_time) = &self.event_time {
+            event_time.observe(at);
+        }
+        let Some(analytics) = self.bucketed_by(host, at) else {
+            return;
+        };
+        analytics.hits.write().record(at, |by_class| {
+            *by_class.entry(StatusClass::of(status)).or_default() += 1;
+        });
+    }
+    /// Counts `host` towards the distinct hosts of `hour` and, when it is
+    /// among the monitored paths, of `path`.
+    pub fn record_visit(&self, host: &str, path: &str, at: DateTime<Utc>) {
+        if let Some(analytics) = self.bucketed_by(host, at)
+            && let Some(hll) = timeseries::bucket_mut(
+                &mut analytics.hosts_by_hour.write(),
+                at.into(),
+                analytics.hours,
+                || HyperLogLog::new(analytics.hll_precision),
+            )
+        {
+            hll.insert(host);
+        }
+        let path: Endpoint = path.parse().unwrap();
+        if self.paths.read().contains(&path) {
+            self.hosts_by_path
+                .write()
+                .entry(path)
+                .or_insert_with(|| HyperLogLog::new(self.hll_precision))
+                .insert(host);
+        }
+    }
+    pub fn record_host_hour_bytes(&self, host: &str, at: DateTime<Utc>, bytes: u64) {
+        let Some(analytics) = self.bucketed_by(host, at) else {
+            return;
+        };
+        analytics
+            .bytes_by_hour_per_host
+            .write()
+            .update(host.parse().unwrap(), |by_hour| {
+                by_hour.add(at.into(), bytes, analytics.hours)
+            });
+    }
+    /// Hosts whose clocks run ahead, with their latest offset.
+    pub fn skewed_hosts(&self) -> Vec<(String, TimeDelta)> {
+        self.skewed_hosts_as_of(Utc::now())
+    }
+    /// Hosts flagged within `--allowed-lateness` of `now`, forgetting the
+    /// rest so a host that fixed its clock drops out.
+    fn skewed_hosts_as_of(&self, now: DateTime<Utc>) -> Vec<(String, TimeDelta)> {
+        let Some(event_time) = &self.event_time else {
+            return Vec::new();
+        };
+        let mut skewed_hosts = self.skewed_hosts.write();
+        while skewed_hosts
+            .peek_lru()
+            .is_some_and(|(_, (_, seen))| *seen < now - event_time.allowed_lateness)
+        {
+            skewed_hosts.pop_lru();
+        }
+        skewed_hosts
+            .iter()
+            .map(|(host, (offset, _))| (host.to_string(), *offset))
+            .collect()
+    }
+    /// Events that arrived after `--allowed-lateness`, unless they are dropped.
+    pub fn late_events(&self) -> usize {
+        self.event_time
+            .as_ref()
+            .map_or(0, |event_time| *event_time.late_events.read())
     }
 
+    pub fn parsed_frequency(&self) -> HashMap<LogFormat, usize> {
+        self.parsed.read().clone()
+    }
+    pub fn parse_error_frequency(&self) -> HashMap<(LogFormat, ParseError), usize> {
+        self.parse_errors.read().clone()
+    }
+    /// The most recently rejected lines, oldest first.
+    pub fn recent_bad_lines(&self) -> Vec<BadLine> {
+        self.bad_lines.read().iter().cloned().collect()
+    }
+    pub fn redelivery_frequency(&self) -> HashMap<String, usize> {
+        self.redeliveries.read().clone()
+    }
     pub fn event_frequency(&self) -> HashMap<u16, usize> {
-        self.events
+        self.events.read().iter().map(|(k, v)| (*k, *v)).collect()
+    }
+    /// Events whose status code was folded out of [`Self::event_frequency`]
+    /// or fell outside 100 to 599.
+    pub fn other_event_count(&self) -> usize {
+        *self.events.read().other()
+    }
+    pub fn status_class_frequency(&self) -> HashMap<StatusClass, usize> {
+        self.status_classes.read().clone()
+    }
+    pub fn top_path_frequency(&self, n: usize) -> Vec<(String, usize)> {
+        self.paths
+            .read()
+            .top(n)
+            .into_iter()
+            .map(|(path, estimate)| (path.to_string(), estimate.count))
+            .collect()
+    }
+    pub fn response_sizes(&self) -> Histogram {
+        self.sizes.read().clone()
+    }
+    pub fn response_sizes_by_class(&self) -> HashMap<StatusClass, Histogram> {
+        self.sizes_by_class.read().clone()
+    }
+    /// Response sizes for each of the top `n` paths.
+    pub fn top_path_response_sizes(&self, n: usize) -> Vec<(String, Histogram)> {
+        let sizes_by_path = self.sizes_by_path.read();
+        self.paths
+            .read()
+            .top(n)
+            .into_iter()
+            .filter_map(|(path, _)| {
+                let sizes = sizes_by_path.get(&path)?.clone();
+                Some((path.to_string(), sizes))
+            })
+            .collect()
+    }
+    /// Estimated distinct hosts for each of the top `n` paths.
+    pub fn top_path_distinct_hosts(&self, n: usize) -> Vec<(String, u64)> {
+        let hosts_by_path = self.hosts_by_path.read();
+        self.paths
+            .read()
+            .top(n)
+            .into_iter()
+            .map(|(path, _)| {
+                let hosts = hosts_by_path.get(&path).map_or(0, HyperLogLog::estimate);
+                (path.to_string(), hosts)
+            })
+            .collect()
+    }
+    /// Hits per bucket for every resolution, oldest first.
+    pub fn hits_per_bucket(&self) -> Vec<(Resolution, Timestamp, usize)> {
+        self.hits
             .read()
             .iter()
-            .map(|(k, v)| (k.to_status(), *v))
+            .flat_map(|(resolution, buckets)| {
+                buckets
+                    .iter()
+                    .map(move |(bucket, by_class)| (resolution, *bucket, by_class.values().sum()))
+            })
             .collect()
     }
-    pub fn top_path_frequency(&self, n: usize) -> Vec<(String, usize)> {
-        let map = self.paths.read();
-        let mut entries: Vec<_> = map.iter().map(|(k, v)| (k.to_string(), *v)).collect();
-        entries.sort_unstable_by(|a, b| b.1.cmp(&a.1));
+    /// Hits per bucket and status class for every resolution, oldest first.
+    pub fn hits_per_bucket_per_class(&self) -> Vec<(Resolution, Timestamp, StatusClass, usize)> {
+        let mut hits: Vec<_> = self
+            .hits
+            .read()
+            .iter()
+            .enumerate()
+            .flat_map(|(i, (resolution, buckets))| {
+                buckets.iter().flat_map(move |(bucket, by_class)| {
+                    by_class
+                        .iter()
+                        .map(move |(class, count)| (i, resolution, *bucket, *class, *count))
+                })
+            })
+            .collect();
+        hits.sort_unstable_by_key(|(i, _, bucket, class, _)| (*i, *bucket, *class));
+        hits.into_iter()
+            .map(|(_, resolution, bucket, class, count)| (resolution, bucket, class, count))
+            .collect()
+    }
+    /// Estimated distinct hosts per hour, oldest first.
+    pub fn distinct_hosts_per_hour(&self) -> Vec<(Timestamp, u64)> {
+        self.hosts_by_hour
+            .read()
+            .iter()
+            .map(|(hour, hll)| (*hour, hll.estimate()))
+            .collect()
+    }
+    /// Most hits any path could have had beyond what the top paths report.
+    pub fn path_error_bound(&self) -> usize {
+        self.paths.read().error_bound()
+    }
+    pub fn top_host_frequency(&self, n: usize) -> Vec<(String, usize)> {
+        let map = self.hosts.read();
+        let mut entries: Vec<_> = map
+            .iter()
+            .map(|(k, v)| (k.as_str().to_owned(), *v))
+            .collect();
+        entries.sort_unstable_by_key(|(_, count)| std::cmp::Reverse(*count));
         entries.truncate(n);
         entries
     }
+    /// Hits from hosts folded out of [`Self::top_host_frequency`].
+    pub fn other_host_hits(&self) -> usize {
+        *self.hosts.read().other()
+    }
+    /// How many keys each capped dimension has folded into `other`.
+    pub fn folded_keys(&self) -> [(&'static str, usize); 6] {
+        [
+            ("status", self.events.read().folded()),
+            ("host", self.hosts.read().folded()),
+            (
+                "host_hour_bytes",
+                self.bytes_by_hour_per_host.read().folded(),
+            ),
+            ("service", self.services.read().folded()),
+            ("level", self.levels.read().folded()),
+            (
+                "extra",
+                self.extras.read().values().map(Capped::folded).sum(),
+            ),
+        ]
+    }
+    pub fn top_referrer_frequency(&self, n: usize) -> Vec<(String, usize)> {
+        self.referrers
+            .read()
+            .top(n)
+            .into_iter()
+            .map(|(referrer, estimate)| (referrer.to_string(), estimate.count))
+            .collect()
+    }
+    pub fn user_agent_family_frequency(&self) -> HashMap<UserAgentFamily, usize> {
+        self.user_agents.read().clone()
+    }
+    /// Lines per service, with those from folded services under `other`.
+    pub fn service_frequency(&self) -> HashMap<String, usize> {
+        capped_frequency(&self.services.read())
+    }
+    /// Lines per value of each extra pattern capture, with those from folded
+    /// values under `other`.
+    pub fn extra_frequency(&self) -> HashMap<String, HashMap<String, usize>> {
+        self.extras
+            .read()
+            .iter()
+            .map(|(field, values)| (field.clone(), capped_frequency(values)))
+            .collect()
+    }
+    /// Lines per level, with those from folded levels under `other`.
+    pub fn level_frequency(&self) -> HashMap<String, usize> {
+        capped_frequency(&self.levels.read())
+    }
+    pub fn syslog_severity_frequency(&self) -> HashMap<Severity, usize> {
+        self.syslog_severities.read().clone()
+    }
+    pub fn top_syslog_app_frequency(&self, n: usize) -> Vec<(String, usize)> {
+        self.syslog_apps
+            .read()
+            .top(n)
+            .into_iter()
+            .map(|(app_name, estimate)| (app_name.to_string(), estimate.count))
+            .collect()
+    }
+    pub fn top_message_frequency(&self, n: usize) -> Vec<(String, usize)> {
+        self.messages
+            .read()
+            .top(n)
+            .into_iter()
+            .map(|(message, estimate)| (message.to_string(), estimate.count))
+            .collect()
+    }
+    /// Bytes per hour for every tracked host, plus `other` once any host has
+    /// been folded.
     pub fn bytes_per_hour_per_host(&self) -> Vec<(String, Vec<(Timestamp, u64)>)> {
         let map = self.bytes_by_hour_per_host.read();
-        map.iter()
-            .map(|(host, by_hour)| {
-                let mut sorted: Vec<_> = by_hour.iter().map(|(t, b)| (*t, *b)).collect();
-                sorted.sort_unstable_by_key(|(ts, _)| *ts);
-                (host.to_string(), sorted)
-            })
-            .collect()
+        let mut hosts: Vec<_> = map
+            .iter()
+            .map(|(host, by_hour)| (host.to_string(), by_hour.sorted()))
+            .collect();
+        if map.folded() > 0 {
+            hosts.push((OTHER.to_owned(), map.other().sorted()));
+        }
+        hosts
     }
-    pub fn export_to_prometheus(&self, metrics: &PromMetrics) {
+    pub fn export_to_prometheus(&self, source: &str, metrics: &PromMetrics) {
+        if self.event_time.is_some() {
+            metrics
+                .late_events
+                .with_label_values(&[source])
+                .inc_by(self.late_events() as u64);
+        }
+
+        for (host, offset) in self.skewed_hosts() {
+            metrics
+                .clock_skew_hosts
+                .with_label_values(&[source, &host])
+                .set(offset.num_seconds());
+        }
+
+        for (format, count) in self.parsed_frequency() {
+            metrics
+                .parsed_lines
+                .with_label_values(&[source, &format.to_string()])
+                .inc_by(count as u64);
+        }
+
+        for ((format, reason), count) in self.parse_error_frequency() {
+            metrics
+                .parse_errors
+                .with_label_values(&[source, &format.to_string(), &reason.to_string()])
+                .inc_by(count as u64);
+        }
+
+        for (subject, count) in self.redelivery_frequency() {
+            metrics
+                .redeliveries
+                .with_label_values(&[source, &subject])
+                .inc_by(count as u64);
+        }
+
         for (event, count) in self.event_frequency().iter() {
             metrics
                 .event_counts
-                .with_label_values(&[&event.to_string()])
+                .with_label_values(&[source, &event.to_string()])
                 .inc_by(*count as u64);
         }
+        let other_events = self.other_event_count();
+        if other_events > 0 {
+            metrics
+                .event_counts
+                .with_label_values(&[source, OTHER])
+                .inc_by(other_events as u64);
+        }
 
-        let host_hits_map = self.hosts.read();
-        let mut host_hits: Vec<_> = host_hits_map.iter().collect();
-        host_hits.sort_unstable_by_key(|(_, count)| std::cmp::Reverse(**count));
-        for (host, count) in host_hits.into_iter().take(10) {
+        for (class, count) in self.status_class_frequency() {
+            metrics
+                .status_class_counts
+                .with_label_values(&[source, &class.to_string()])
+                .inc_by(count as u64);
+        }
+
+        for (host, count) in self.top_host_frequency(10) {
             metrics
                 .host_hits
-                .with_label_values(&[host.as_str()])
-                .inc_by(*count as u64);
+                .with_label_values(&[source, &host])
+                .inc_by(count as u64);
+        }
+        let other_hosts = self.other_host_hits();
+        if other_hosts > 0 {
+            metrics
+                .host_hits
+                .with_label_values(&[source, OTHER])
+                .inc_by(other_hosts as u64);
         }
 
+        for (dimension, folded) in self.folded_keys() {
+            metrics
+                .folded_keys
+                .with_label_values(&[source, dimension])
+                .inc_by(folded as u64);
+        }
+
+        metrics
+            .path_hits_error_bound
+            .with_label_values(&[source])
+            .set(self.path_error_bound() as i64);
+
         let top_paths = self.top_path_frequency(5);
         for (path, count) in top_paths {
             metrics
                 .path_hits
-                .with_label_values(&[&path])
+                .with_label_values(&[source, &path])
+                .inc_by(count as u64);
+        }
+
+        let precision = self.hll_precision.to_string();
+        metrics
+            .hll_relative_error
+            .with_label_values(&[source, &precision])
+            .set(HyperLogLog::relative_error(self.hll_precision));
+        for (path, hosts) in self.top_path_distinct_hosts(5) {
+            metrics
+                .distinct_hosts_per_path
+                .with_label_values(&[source, &precision, &path])
+                .set(hosts as i64);
+        }
+
+        for (resolution, bucket, count) in self.hits_per_bucket() {
+            let ts = bucket.into_utc().format("%Y%m%d%H%M").to_string();
+            metrics
+                .bucket_hits
+                .with_label_values(&[source, &resolution.to_string(), &ts])
+                .set(count as i64);
+        }
+
+        for (resolution, bucket, class, count) in self.hits_per_bucket_per_class() {
+            let ts = bucket.into_utc().format("%Y%m%d%H%M").to_string();
+            metrics
+                .bucket_class_hits
+                .with_label_values(&[source, &resolution.to_string(), &ts, &class.to_string()])
+                .set(count as i64);
+        }
+
+        for (hour, hosts) in self.distinct_hosts_per_hour() {
+            let ts = hour.into_utc().format("%Y%m%d%H").to_string();
+            metrics
+                .distinct_hosts_per_hour
+                .with_label_values(&[source, &precision, &ts])
+                .set(hosts as i64);
+        }
+
+        let sizes = self.response_sizes();
+        if sizes.count() > 0 {
+            metrics.response_sizes.set(&[source], &sizes);
+        }
+        for (class, sizes) in self.response_sizes_by_class() {
+            metrics
+                .response_sizes_by_class
+                .set(&[source, &class.to_string()], &sizes);
+        }
+        for (path, sizes) in self.top_path_response_sizes(5) {
+            metrics.response_sizes_by_path.set(&[source, &path], &sizes);
+        }
+
+        for (referrer, count) in self.top_referrer_frequency(5) {
+            metrics
+                .referrer_hits
+                .with_label_values(&[source, &referrer])
+                .inc_by(count as u64);
+        }
+
+        for (family, count) in self.user_agent_family_frequency() {
+            metrics
+                .user_agent_hits
+                .with_label_values(&[source, &family.to_string()])
+                .inc_by(count as u64);
+        }
+
+        for (service, count) in self.service_frequency() {
+            metrics
+                .service_counts
+                .with_label_values(&[source, &service])
+                .inc_by(count as u64);
+        }
+
+        for (level, count) in self.level_frequency() {
+            metrics
+                .level_counts
+                .with_label_values(&[source, &level])
+                .inc_by(count as u64);
+        }
+
+        for (severity, count) in self.syslog_severity_frequency() {
+            metrics
+                .syslog_severity_counts
+                .with_label_values(&[source, &severity.to_string()])
+                .inc_by(count as u64);
+        }
+
+        for (app_name, count) in self.top_syslog_app_frequency(10) {
+            metrics
+                .syslog_app_counts
+                .with_label_values(&[source, &app_name])
+                .inc_by(count as u64);
+        }
+
+        for (field, values) in self.extra_frequency() {
+            for (value, count) in values {
+                metrics
+                    .extra_field_counts
+                    .with_label_values(&[source, &field, &value])
+                    .inc_by(count as u64);
+            }
+        }
+
+        for (message, count) in self.top_message_frequency(5) {
+            metrics
+                .message_counts
+                .with_label_values(&[source, &message])
                 .inc_by(count as u64);
         }
 
@@ -160,7 +1105,7 @@ impl Analytics {
                     let ts = hour.into_utc().format("%Y%m%d%H").to_string();
                     metrics
                         .bytes_per_hour_per_host
-                        .with_label_values(&[host, &ts])
+                        .with_label_values(&[source, host, &ts])
                         .set(*bytes as i64);
                 }
             }
@@ -186,6 +1131,96 @@ mod tests {
         assert_eq!(freq.get(&404), Some(&1));
     }
 
+    #[test]
+    fn record_event_keeps_every_status_and_its_class() {
+        let analytics = Analytics::new(&LimitArgs {
+            max_statuses: 3,
+            ..LimitArgs::default()
+        });
+        for status in [201, 201, 201, 302, 302, 302, 401, 429, 503, 999] {
+            analytics.record_event(status);
+        }
+
+        let freq = analytics.event_frequency();
+        assert_that!(freq.get(&201)).is_equal_to(Some(&3));
+        assert_that!(freq.get(&302)).is_equal_to(Some(&3));
+        assert_that!(freq.get(&503)).is_equal_to(Some(&1));
+        assert_that!(analytics.other_event_count()).is_equal_to(3);
+        let classes = analytics.status_class_frequency();
+        assert_that!(classes.get(&StatusClass::of(400))).is_equal_to(Some(&2));
+        assert_that!(classes.get(&StatusClass::of(500))).is_equal_to(Some(&1));
+        assert_that!(classes.len()).is_equal_to(4);
+    }
+
+    #[test]
+    fn record_parsed_counts_per_format() {
+        let analytics = Analytics::default();
+        analytics.record_parsed(LogFormat::Apache);
+        analytics.record_parsed(LogFormat::Json);
+        analytics.record_parsed(LogFormat::Json);
+
+        let parsed = analytics.parsed_frequency();
+        assert_eq!(parsed.get(&LogFormat::Apache), Some(&1));
+        assert_eq!(parsed.get(&LogFormat::Json), Some(&2));
+        assert_eq!(parsed.get(&LogFormat::Combined), None);
+    }
+
+    #[test]
+    fn record_parse_error_counts_and_samples() {
+        let analytics = Analytics::default();
+        analytics.record_parse_error(LogFormat::Apache, ParseError::Status, "bad status");
+        analytics.record_parse_error(LogFormat::Apache, ParseError::Status, "bad status");
+        analytics.record_parse_error(LogFormat::Json, ParseError::Structure, "{");
+
+        let errors = analytics.parse_error_frequency();
+        assert_eq!(
+            errors.get(&(LogFormat::Apache, ParseError::Status)),
+            Some(&2)
+        );
+        assert_eq!(
+            errors.get(&(LogFormat::Json, ParseError::Structure)),
+            Some(&1)
+        );
+        assert_eq!(
+            analytics.recent_bad_lines().last(),
+            Some(&BadLine {
+                format: LogFormat::Json,
+                reason: ParseError::Structure,
+                line: "{".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn recent_bad_lines_is_bounded() {
+        let analytics = Analytics::default();
+        for i in 0..(MAX_BAD_LINES + 10) {
+            analytics.record_parse_error(LogFormat::Apache, ParseError::Structure, &i.to_string());
+        }
+        analytics.record_parse_error(
+            LogFormat::Apache,
+            ParseError::Structure,
+            &"x".repeat(MAX_BAD_LINE_LEN * 2),
+        );
+
+        let bad_lines = analytics.recent_bad_lines();
+        assert_that!(bad_lines.len()).is_equal_to(MAX_BAD_LINES);
+        assert_that!(bad_lines[0].line.as_str()).is_equal_to("11");
+        assert_that!(bad_lines.last().unwrap().line.len()).is_equal_to(MAX_BAD_LINE_LEN);
+    }
+
+    #[test]
+    fn record_redelivery_counts_per_subject() {
+        let analytics = Analytics::default();
+        analytics.record_redelivery("logs.web");
+        analytics.record_redelivery("logs.web");
+        analytics.record_redelivery("logs.api");
+
+        let redeliveries = analytics.redelivery_frequency();
+        assert_that!(redeliveries.get("logs.web")).is_equal_to(Some(&2));
+        assert_that!(redeliveries.get("logs.api")).is_equal_to(Some(&1));
+    }
+
     #[test]
     fn record_path_counts() {
         let analytics = Analytics::default();
@@ -198,12 +1233,336 @@ mod tests {
         assert_eq!(paths[1], ("/bar".into(), 1));
     }
 
+    #[test]
+    fn record_visit_estimates_distinct_hosts() {
+        let analytics = Analytics::default();
+        let ts = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        for (host, path) in [("a", "/foo"), ("b", "/foo"), ("a", "/foo"), ("c", "/bar")] {
+            analytics.record_path(path);
+            analytics.record_visit(host, path, ts);
+        }
+
+        assert_that!(analytics.distinct_hosts_per_hour()).is_equal_to(vec![(ts.into(), 3)]);
+        assert_that!(analytics.top_path_distinct_hosts(2))
+            .is_equal_to(vec![("/foo".into(), 2), ("/bar".into(), 1)]);
+    }
+
+    #[test]
+    fn record_response_size_builds_histograms() {
+        let analytics = Analytics::new(&LimitArgs {
+            size_buckets: vec![1_000, 100],
+            ..LimitArgs::default()
+        });
+        for (path, status, bytes) in [("/a", 200, 50), ("/a", 200, 500), ("/b", 404, 5_000)] {
+            analytics.record_path(path);
+            analytics.record_response_size(path, status, bytes);
+        }
+
+        let sizes = analytics.response_sizes();
+        assert_that!(sizes.cumulative()).is_equal_to(vec![(100, 1), (1_000, 2)]);
+        assert_that!(sizes.count()).is_equal_to(3);
+        let by_class = analytics.response_sizes_by_class();
+        assert_that!(by_class[&StatusClass::of(404)].sum()).is_equal_to(5_000);
+        let by_path = analytics.top_path_response_sizes(1);
+        assert_that!(by_path[0].0.as_str()).is_equal_to("/a");
+        assert_that!(by_path[0].1.count()).is_equal_to(2);
+    }
+
+    #[test]
+    fn record_referrer_counts() {
+        let analytics = Analytics::default();
+        analytics.record_referrer("https://a.example/");
+        analytics.record_referrer("https://b.example/");
+        analytics.record_referrer("https://a.example/");
+
+        let referrers = analytics.top_referrer_frequency(1);
+        assert_eq!(referrers, vec![("https://a.example/".into(), 2)]);
+    }
+
+    #[test]
+    fn top_referrers_survive_a_burst_of_one_offs() {
+        let analytics = Analytics::default();
+        for _ in 0..50 {
+            analytics.record_referrer("https://a.example/");
+        }
+        for i in 0..(REFERRER_COUNTERS * 2) {
+            analytics.record_referrer(&format!("https://once.example/{i}"));
+        }
+
+        let (referrer, count) = analytics.top_referrer_frequency(1).remove(0);
+        assert_that!(referrer.as_str()).is_equal_to("https://a.example/");
+        assert_that!(count).is_equal_to(50);
+    }
+
+    #[test]
+    fn record_user_agent_groups_by_family() {
+        let analytics = Analytics::default();
+        analytics.record_user_agent(
+            "Mozilla/5.0 (Windows NT 10.0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36",
+        );
+        analytics.record_user_agent(
+            "Mozilla/5.0 (Windows NT 10.0) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0 Safari/537.36 Edg/126.0",
+        );
+        analytics.record_user_agent(
+            "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0",
+        );
+        analytics.record_user_agent("Mozilla/5.0 (compatible; Googlebot/2.1)");
+        analytics.record_user_agent("curl/8.5.0");
+        analytics.record_user_agent("curl/8.6.0");
+
+        let families = analytics.user_agent_family_frequency();
+        assert_eq!(families.get(&UserAgentFamily::Chrome), Some(&1));
+        assert_eq!(families.get(&UserAgentFamily::Edge), Some(&1));
+        assert_eq!(families.get(&UserAgentFamily::Firefox), Some(&1));
+        assert_eq!(families.get(&UserAgentFamily::Bot), Some(&1));
+        assert_eq!(families.get(&UserAgentFamily::Curl), Some(&2));
+    }
+
+    #[test]
+    fn record_structured_log_counts() {
+        let analytics = Analytics::default();
+        analytics.record_service("api");
+        analytics.record_service("api");
+        analytics.record_service("db");
+        analytics.record_level("warn");
+        analytics.record_level("WARN");
+        analytics.record_message("Cache miss");
+        analytics.record_message("Token refreshed");
+        analytics.record_message("Cache miss");
+
+        let services = analytics.service_frequency();
+        assert_eq!(services.get("api"), Some(&2));
+        assert_eq!(services.get("db"), Some(&1));
+        assert_eq!(analytics.level_frequency().get("WARN"), Some(&2));
+        assert_eq!(
+            analytics.top_message_frequency(1),
+            vec![("Cache miss".into(), 2)]
+        );
+    }
+
+    #[test]
+    fn extra_captures_are_counted_per_field_and_value() {
+        let analytics = Analytics::new(&LimitArgs {
+            max_extra_values: 1,
+            ..LimitArgs::default()
+        });
+        for (field, value) in [
+            ("tenant", "acme"),
+            ("tenant", "acme"),
+            ("tenant", "globex"),
+            ("region", "eu"),
+        ] {
+            analytics.record_extra(field, value);
+        }
+
+        let extras = analytics.extra_frequency();
+        assert_that!(extras["tenant"].get("globex")).is_equal_to(Some(&1));
+        assert_that!(extras["tenant"].get("other")).is_equal_to(Some(&2));
+        assert_that!(extras["region"].get("eu")).is_equal_to(Some(&1));
+        assert_that!(analytics.folded_keys()[5]).is_equal_to(("extra", 1));
+    }
+
+    #[test]
+    fn services_and_levels_past_the_limit_are_folded_into_other() {
+        let analytics = Analytics::new(&LimitArgs {
+            max_services: 1,
+            max_levels: 1,
+            ..LimitArgs::default()
+        });
+        for service in ["api", "api", "db", "cache"] {
+            analytics.record_service(service);
+        }
+        analytics.record_level("INFO");
+        analytics.record_level("x".repeat(10).as_str());
+
+        let services = analytics.service_frequency();
+        assert_that!(services.get("cache")).is_equal_to(Some(&1));
+        assert_that!(services.get("other")).is_equal_to(Some(&3));
+        assert_that!(analytics.level_frequency().len()).is_equal_to(2);
+        assert_that!(&analytics.folded_keys()[3..5])
+            .is_equal_to(&[("service", 2), ("level", 1)][..]);
+    }
+
+    #[test]
+    fn record_syslog_dimensions() {
+        let analytics = Analytics::default();
+        analytics.record_syslog_severity(Severity::Error);
+        analytics.record_syslog_severity(Severity::Error);
+        analytics.record_syslog_severity(Severity::Info);
+        analytics.record_syslog_app("sshd");
+
+        let severities = analytics.syslog_severity_frequency();
+        assert_that!(severities.get(&Severity::Error)).is_equal_to(Some(&2));
+        assert_that!(severities.get(&Severity::Info)).is_equal_to(Some(&1));
+        assert_that!(analytics.top_syslog_app_frequency(5))
+            .is_equal_to(vec![("sshd".to_owned(), 1)]);
+    }
+
+    #[test]
+    fn record_hit_counts_per_bucket_and_class() {
+        let hourly: Resolution = "1h:6".parse().unwrap();
+        let daily: Resolution = "1d:1".parse().unwrap();
+        let analytics = Analytics::new(&LimitArgs {
+            resolutions: vec![hourly, daily],
+            ..LimitArgs::default()
+        });
+        let noon = Utc.with_ymd_and_hms(2024, 1, 1, 12, 30, 0).unwrap();
+        let one = noon + Duration::hours(1);
+        let day = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for (ts, status) in [(noon, 200), (noon, 204), (noon, 503), (one, 404)] {
+            analytics.record_hit("host1", ts, status);
+        }
+
+        assert_that!(analytics.hits_per_bucket()).is_equal_to(vec![
+            (hourly, noon.into(), 3),
+            (hourly, one.into(), 1),
+            (daily, day.into(), 4),
+        ]);
+        assert_that!(&analytics.hits_per_bucket_per_class()[..3]).is_equal_to(
+            &[
+                (hourly, noon.into(), StatusClass::of(200), 2),
+                (hourly, noon.into(), StatusClass::of(500), 1),
+                (hourly, one.into(), StatusClass::of(404), 1),
+            ][..],
+        );
+        assert_that!(StatusClass::of(503).to_string()).is_equal_to("5xx".to_owned());
+    }
+
+    #[test]
+    fn events_behind_the_watermark_are_kept_out_of_buckets() {
+        let analytics = Analytics::new(&LimitArgs {
+            resolutions: vec!["1h:6".parse().unwrap()],
+            ..LimitArgs::default()
+        });
+        let noon = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let replayed = noon - Duration::days(1);
+        // Out of order but within the hour allowed, then a replayed line.
+        for at in [
+            noon,
+            noon + Duration::hours(2),
+            noon + Duration::minutes(90),
+            replayed,
+        ] {
+            analytics.record_hit("host1", at, 200);
+            analytics.record_host_hour_bytes("host1", at, 100);
+        }
+
+        let hours: Vec<_> = analytics
+            .hits_per_bucket()
+            .into_iter()
+            .map(|(_, hour, count)| (hour, count))
+            .collect();
+        assert_that!(hours).is_equal_to(vec![
+            (noon.into(), 1),
+            ((noon + Duration::hours(1)).into(), 1),
+            ((noon + Duration::hours(2)).into(), 1),
+        ]);
+        assert_that!(analytics.late_events()).is_equal_to(1);
+        assert!(
+            !analytics.bytes_per_hour_per_host()[0]
+                .1
+                .contains(&(replayed.into(), 100))
+        );
+    }
+
+    #[test]
+    fn hosts_ahead_of_the_wall_clock_are_flagged_not_bucketed() {
+        let analytics = Analytics::default();
+        let now = Utc::now();
+        let future = now + Duration::days(365 * 3);
+        // Only a hit, every dropped event flags its host.
+        analytics.record_hit("skewed", future, 200);
+        analytics.record_hit("fine", now, 200);
+        analytics.record_host_hour_bytes("fine", now, 100);
+
+        assert_that!(analytics.late_events()).is_equal_to(0);
+        let hosts: Vec<_> = analytics
+            .bytes_per_hour_per_host()
+            .into_iter()
+            .map(|(host, _)| host)
+            .collect();
+        assert_that!(hosts).is_equal_to(vec!["fine".to_owned()]);
+        let skewed = analytics.skewed_hosts();
+        assert_that!(skewed.len()).is_equal_to(1);
+        assert_that!(skewed[0].0.as_str()).is_equal_to("skewed");
+        assert_that!(skewed[0].1).is_greater_than(Duration::days(365 * 3 - 1));
+    }
+
+    /// Hits in the hourly series, whichever hours they fell in.
+    fn hourly_hits(analytics: &Analytics) -> usize {
+        analytics
+            .hits_per_bucket()
+            .into_iter()
+            .filter(|(resolution, _, _)| resolution.width == Duration::hours(1))
+            .map(|(_, _, count)| count)
+            .sum()
+    }
+
+    #[test]
+    fn hosts_ahead_of_the_watermark_are_flagged_not_bucketed() {
+        let analytics = Analytics::default();
+        let now = Utc::now();
+        analytics.record_hit("fine", now - Duration::hours(1), 200);
+        // Within the skew allowed by the wall clock, but not by the watermark.
+        analytics.record_visit("ahead", "/", now + Duration::minutes(2));
+        analytics.record_hit("fine", now - Duration::minutes(59), 200);
+
+        assert_that!(hourly_hits(&analytics)).is_equal_to(2);
+        let skewed = analytics.skewed_hosts();
+        assert_that!(skewed.len()).is_equal_to(1);
+        assert_that!(skewed[0].0.as_str()).is_equal_to("ahead");
+        assert_that!(skewed[0].1).is_greater_than(Duration::minutes(61));
+        assert_that!(analytics.skewed_hosts_as_of(now + Duration::hours(2))).is_empty();
+    }
+
+    #[test]
+    fn a_backlog_catching_up_is_never_skewed() {
+        let analytics = Analytics::default();
+        let now = Utc::now();
+        for minutes in [0, 10, 11, 12, 30, 59] {
+            let at = now - Duration::hours(1) + Duration::minutes(minutes);
+            analytics.record_hit("h", at, 200);
+        }
+        for _ in 0..5 {
+            analytics.record_hit("live", now, 200);
+        }
+
+        assert_that!(hourly_hits(&analytics)).is_equal_to(11);
+        assert_that!(analytics.skewed_hosts()).is_empty();
+        assert_that!(analytics.late_events()).is_equal_to(0);
+    }
+
+    #[test]
+    fn late_events_can_be_routed_to_their_own_source() {
+        let sources = Sources::new(LimitArgs {
+            late_events: LatePolicy::Route,
+            ..LimitArgs::default()
+        });
+        let noon = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        let analytics = sources.get("web");
+        analytics.record_hit("host1", noon, 200);
+        analytics.record_hit("host1", noon - Duration::days(1), 404);
+
+        assert_that!(analytics.late_events()).is_equal_to(1);
+        let late = sources.get("web.late");
+        let late_hits: Vec<_> = late
+            .hits_per_bucket()
+            .into_iter()
+            .map(|(resolution, _, count)| (resolution.to_string(), count))
+            .collect();
+        assert_that!(late_hits).is_equal_to(vec![
+            ("1m".to_owned(), 1),
+            ("1h".to_owned(), 1),
+            ("1d".to_owned(), 1),
+        ]);
+    }
+
     #[test]
     fn record_host_hour_bytes_counts() {
         let analytics = Analytics::default();
         let ts = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
-        analytics.record_host_hour_bytes("host1", ts.into(), 100);
-        analytics.record_host_hour_bytes("host1", ts.into(), 200);
+        analytics.record_host_hour_bytes("host1", ts, 100);
+        analytics.record_host_hour_bytes("host1", ts, 200);
 
         let result = analytics.bytes_per_hour_per_host();
         assert!(result.contains(&("host1".into(), vec![(ts.into(), 300)])));
@@ -214,10 +1573,62 @@ mod tests {
         let ts = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
         for i in 0..1000 {
             let ts = ts + Duration::hours(i);
-            analytics.record_host_hour_bytes("host1", ts.into(), 200);
+            analytics.record_host_hour_bytes("host1", ts, 200);
+        }
+
+        let result = &analytics.bytes_per_hour_per_host()[0].1;
+        assert_that!(result.len()).is_in_range(0..=MAX_HOURS);
+    }
+
+    #[test]
+    fn batch_sources_keep_every_hour_and_future_events() {
+        let sources = Sources::batch(LimitArgs::default());
+        let analytics = sources.get("archive");
+        let ts = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        for i in 0..24 {
+            analytics.record_host_hour_bytes("host1", ts + Duration::hours(i), 100);
         }
+        let future = Utc::now() + Duration::days(365);
+        analytics.record_host_hour_bytes("host1", future, 100);
+        analytics.record_host_hour_bytes("host1", ts, 100);
 
         let result = &analytics.bytes_per_hour_per_host()[0].1;
-        assert_that!(result.len()).is_in_range(0..=MAX_HOURS.get());
+        assert_that!(result.len()).is_equal_to(25);
+        assert_that!(result[0]).is_equal_to((ts.into(), 200));
+        assert_that!(analytics.skewed_hosts()).is_empty();
+    }
+
+    #[test]
+    fn hosts_past_the_limit_are_folded_into_other() {
+        let analytics = Analytics::new(&LimitArgs {
+            max_hosts: 2,
+            max_byte_hosts: 2,
+            ..LimitArgs::default()
+        });
+        let ts = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 0).unwrap();
+        for _ in 0..5 {
+            analytics.record_host("10.0.0.1");
+        }
+        analytics.record_host_hour_bytes("10.0.0.1", ts, 1_000);
+        for i in 2..6 {
+            analytics.record_host(&format!("10.0.0.{i}"));
+            analytics.record_host_hour_bytes(&format!("10.0.0.{i}"), ts, 10);
+        }
+
+        assert_that!(analytics.top_host_frequency(1)).is_equal_to(vec![("10.0.0.1".into(), 5)]);
+        assert_that!(analytics.other_host_hits()).is_equal_to(3);
+        assert_that!(analytics.folded_keys()).is_equal_to([
+            ("status", 0),
+            ("host", 3),
+            ("host_hour_bytes", 3),
+            ("service", 0),
+            ("level", 0),
+            ("extra", 0),
+        ]);
+        assert!(
+            analytics
+                .bytes_per_hour_per_host()
+                .contains(&("other".into(), vec![(ts.into(), 30)]))
+        );
     }
 }
@@ -0,0 +1,141 @@
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::read::MultiGzDecoder;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    AGGREGATOR_BUFFER_SIZE, INGEST_BUFFER_SIZE,
+    analytics::{LimitArgs, Sources},
+    ingest::Chunk,
+    parser::LogFormat,
+    report::{self, Report, ReportFormat},
+    spawn_aggregator, spawn_workers,
+};
+
+const LINES_PER_CHUNK: usize = 10_000;
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(clap::Args, Debug)]
+pub struct AnalyzeArgs {
+    /// Log files to read, gzipped or not. Reads stdin when none or `-` is given
+    files: Vec<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = LogFormat::Auto)]
+    format: LogFormat,
+
+    /// TOML file of named-capture regexes, used by `--format pattern`
+    #[arg(long, required_if_eq("format", "pattern"))]
+    patterns: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = ReportFormat::Table)]
+    output: ReportFormat,
+
+    /// How many paths and hosts to list
+    #[arg(long, default_value_t = 10)]
+    top: usize,
+
+    #[command(flatten)]
+    pub limits: LimitArgs,
+}
+
+/// Runs `files` through the same workers and aggregation as the server and
+/// prints one report per file once they have all been read.
+pub async fn run(args: AnalyzeArgs) -> Result<(), Box<dyn Error>> {
+    let parser = args.format.parser(args.patterns.as_deref())?;
+    let files = if args.files.is_empty() {
+        vec![PathBuf::from("-")]
+    } else {
+        args.files
+    };
+
+    let sources = Arc::new(Sources::batch(args.limits));
+    let (ingest_tx, ingest_rx) = mpsc::channel(INGEST_BUFFER_SIZE);
+    let (aggregator_tx, aggregator_rx) = mpsc::channel(AGGREGATOR_BUFFER_SIZE);
+    let worker_handle = spawn_workers(ingest_rx, aggregator_tx, parser, Vec::new(), None);
+    let aggregator_handle = spawn_aggregator(aggregator_rx, &sources);
+
+    let sources_read = files.clone();
+    tokio::task::spawn_blocking(move || -> Result<(), String> {
+        for path in &sources_read {
+            feed(path, &ingest_tx).map_err(|e| format!("{}: {e}", path.display()))?;
+        }
+        Ok(())
+    })
+    .await??;
+    worker_handle.await?;
+    aggregator_handle.await?;
+
+    let reports: Vec<_> = files
+        .iter()
+        .map(|path| {
+            let source = source_name(path);
+            Report::new(&source, &sources.get(&source), args.top)
+        })
+        .collect();
+    report::write(&reports, args.output, &mut io::stdout().lock())?;
+    Ok(())
+}
+
+fn source_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        "stdin".to_owned()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Opens `path`, or stdin for `-`, decompressing it when it starts with the
+/// gzip magic number.
+fn open(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let input: Box<dyn Read> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path)?)
+    };
+    let mut input = BufReader::new(input);
+    if input.fill_buf()?.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(input))))
+    } else {
+        Ok(Box::new(input))
+    }
+}
+
+fn feed(path: &Path, tx: &Sender<Chunk>) -> io::Result<()> {
+    let subject = source_name(path);
+    let mut input = open(path)?;
+    let mut payload = String::new();
+    let mut lines = 0;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let eof = input.read_until(b'\n', &mut line)? == 0;
+        if !eof {
+            payload.push_str(&String::from_utf8_lossy(&line));
+            if !payload.ends_with('\n') {
+                payload.push('\n');
+            }
+            lines += 1;
+        }
+        if (eof && lines > 0) || lines == LINES_PER_CHUNK {
+            let chunk = Chunk {
+                subject: subject.clone(),
+                payload: std::mem::take(&mut payload),
+                redelivered: false,
+                syslog: None,
+                ack: None,
+            };
+            tx.blocking_send(chunk)
+                .map_err(|_| io::Error::other("workers stopped"))?;
+            lines = 0;
+        }
+        if eof {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+};
+
+/// A value that can be ranked by weight and merged into another.
+pub trait Weighted: Default {
+    fn weight(&self) -> u64;
+    fn absorb(&mut self, other: Self);
+}
+
+impl Weighted for usize {
+    fn weight(&self) -> u64 {
+        *self as u64
+    }
+    fn absorb(&mut self, other: Self) {
+        *self += other;
+    }
+}
+
+/// A map holding at most `capacity` keys. Once full, each new key evicts the
+/// lightest one, whose value is folded into a shared overflow bucket.
+///
+/// As in [`SpaceSaving`](crate::space_saving::SpaceSaving), the new key is
+/// ranked as if it had the evicted key's weight on top of its own, so a key
+/// arriving after the map filled up outlasts the one-offs around it. Values
+/// themselves stay exact from the moment their key got in.
+#[derive(Debug)]
+pub struct Capped<K, V> {
+    capacity: usize,
+    /// Each value with the weight its key took over when it got in.
+    entries: HashMap<K, (V, u64)>,
+    by_weight: BTreeSet<(u64, K)>,
+    other: V,
+    folded: usize,
+}
+
+impl<K: Hash + Ord + Clone, V: Weighted> Capped<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a capped map needs room for at least one key");
+        Self {
+            capacity,
+            entries: HashMap::with_capacity(capacity),
+            by_weight: BTreeSet::new(),
+            other: V::default(),
+            folded: 0,
+        }
+    }
+
+    /// Applies `f` to the value for `key`, making room for it first if needed.
+    pub fn update(&mut self, key: K, f: impl FnOnce(&mut V)) {
+        if let Some((value, taken_over)) = self.entries.get_mut(&key) {
+            self.by_weight
+                .remove(&(value.weight() + *taken_over, key.clone()));
+            f(value);
+            self.by_weight.insert((value.weight() + *taken_over, key));
+            return;
+        }
+        let mut taken_over = 0;
+        if self.entries.len() == self.capacity
+            && let Some((weight, lightest)) = self.by_weight.pop_first()
+            && let Some((value, _)) = self.entries.remove(&lightest)
+        {
+            self.other.absorb(value);
+            self.folded += 1;
+            taken_over = weight;
+        }
+        let mut value = V::default();
+        f(&mut value);
+        self.by_weight
+            .insert((value.weight() + taken_over, key.clone()));
+        self.entries.insert(key, (value, taken_over));
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.entries.iter().map(|(key, (value, _))| (key, value))
+    }
+
+    /// Everything recorded against keys that have since been evicted.
+    pub fn other(&self) -> &V {
+        &self.other
+    }
+
+    /// Applies `f` to [`Self::other`] directly, for values that never get a
+    /// key of their own.
+    pub fn update_other(&mut self, f: impl FnOnce(&mut V)) {
+        f(&mut self.other);
+    }
+
+    /// How many keys have been folded into [`Self::other`].
+    pub fn folded(&self) -> usize {
+        self.folded
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    #[test]
+    fn keeps_everything_below_capacity() {
+        let mut map = Capped::<&str, usize>::new(2);
+        map.update("a", |n| *n += 1);
+        map.update("b", |n| *n += 1);
+        map.update("a", |n| *n += 1);
+
+        let mut entries: Vec<_> = map.iter().map(|(k, v)| (*k, *v)).collect();
+        entries.sort_unstable();
+        assert_that!(entries).is_equal_to(vec![("a", 2), ("b", 1)]);
+        assert_that!(*map.other()).is_equal_to(0);
+        assert_that!(map.folded()).is_equal_to(0);
+    }
+
+    #[test]
+    fn folds_the_lightest_keys_into_other() {
+        let mut map = Capped::<String, usize>::new(3);
+        for _ in 0..60 {
+            map.update("popular".to_owned(), |n| *n += 1);
+        }
+        for i in 0..100 {
+            map.update(format!("once-{i}"), |n| *n += 1);
+        }
+
+        assert_that!(map.iter().count()).is_equal_to(3);
+        assert_that!(map.entries.get("popular").map(|(n, _)| *n)).is_equal_to(Some(60));
+        assert_that!(map.folded()).is_equal_to(98);
+        let kept: usize = map.iter().map(|(_, n)| n).sum();
+        assert_that!(kept + map.other()).is_equal_to(160);
+    }
+
+    #[test]
+    fn heavy_hitters_survive_a_burst_of_one_offs() {
+        let mut map = Capped::<String, usize>::new(100);
+        for i in 0..100 {
+            map.update(format!("early-{i}"), |n| *n += 1);
+        }
+        for i in 0..100_000 {
+            if i % 10 == 0 {
+                map.update("heavy".to_owned(), |n| *n += 1);
+            }
+            map.update(format!("once-{i}"), |n| *n += 1);
+        }
+
+        let heavy = map.iter().find(|(k, _)| k.as_str() == "heavy");
+        assert_that!(heavy.map(|(_, n)| *n)).is_equal_to(Some(10_000));
+        let kept: usize = map.iter().map(|(_, n)| n).sum();
+        assert_that!(kept + map.other()).is_equal_to(110_100);
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+/// Counts of values falling into fixed buckets, as a Prometheus histogram
+/// reports them. `bounds` are inclusive upper bounds in ascending order,
+/// anything above the last one lands in an implicit `+Inf` bucket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    bounds: Arc<[u64]>,
+    counts: Box<[u64]>,
+    sum: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: Arc<[u64]>) -> Self {
+        Self {
+            counts: vec![0; bounds.len() + 1].into_boxed_slice(),
+            bounds,
+            sum: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: u64) {
+        let bucket = self.bounds.partition_point(|bound| *bound < value);
+        self.counts[bucket] += 1;
+        self.sum = self.sum.saturating_add(value);
+    }
+
+    /// Every bound with the number of values at or below it, `+Inf` left out.
+    pub fn cumulative(&self) -> Vec<(u64, u64)> {
+        self.bounds
+            .iter()
+            .zip(self.counts.iter().scan(0, |total, count| {
+                *total += count;
+                Some(*total)
+            }))
+            .map(|(bound, total)| (*bound, total))
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> u64 {
+        self.sum
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    #[test]
+    fn values_land_in_the_first_bucket_that_holds_them() {
+        let mut histogram = Histogram::new(Arc::from([100, 1_000]));
+        for value in [0, 100, 101, 1_000, 5_000] {
+            histogram.observe(value);
+        }
+
+        assert_that!(histogram.cumulative()).is_equal_to(vec![(100, 2), (1_000, 4)]);
+        assert_that!(histogram.count()).is_equal_to(5);
+        assert_that!(histogram.sum()).is_equal_to(6_201);
+    }
+}
@@ -0,0 +1,96 @@
+use std::hash::{BuildHasher, BuildHasherDefault, DefaultHasher, Hash};
+
+/// HyperLogLog distinct counter (Flajolet et al.) over `2^precision` one
+/// byte registers. Estimates are off by about `1.04 / sqrt(2^precision)`,
+/// 1.6% at the default precision of 12.
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    precision: u8,
+    registers: Box<[u8]>,
+}
+
+impl HyperLogLog {
+    pub const MIN_PRECISION: u8 = 4;
+    pub const MAX_PRECISION: u8 = 16;
+
+    pub fn new(precision: u8) -> Self {
+        assert!(
+            (Self::MIN_PRECISION..=Self::MAX_PRECISION).contains(&precision),
+            "HyperLogLog precision must be between 4 and 16"
+        );
+        Self {
+            precision,
+            registers: vec![0; 1 << precision].into_boxed_slice(),
+        }
+    }
+
+    pub fn insert(&mut self, item: &(impl Hash + ?Sized)) {
+        // Seeded identically everywhere, so the same item always lands in
+        // the same register.
+        let hash = BuildHasherDefault::<DefaultHasher>::default().hash_one(item);
+        let index = (hash >> (64 - self.precision)) as usize;
+        // The sentinel bit caps the rank for hashes whose remaining bits are
+        // all zero.
+        let rest = (hash << self.precision) | (1 << (self.precision - 1));
+        let rank = rest.leading_zeros() as u8 + 1;
+        let register = &mut self.registers[index];
+        *register = (*register).max(rank);
+    }
+
+    /// Relative standard error of estimates at `precision`.
+    pub fn relative_error(precision: u8) -> f64 {
+        1.04 / f64::from(1u32 << precision).sqrt()
+    }
+
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1.0 + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-i32::from(*r)))
+            .sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // Linear counting is more accurate while many registers are unset.
+        let estimate = if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        };
+        estimate.round() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    #[test]
+    fn small_sets_are_counted_almost_exactly() {
+        let mut hll = HyperLogLog::new(12);
+        assert_that!(hll.estimate()).is_equal_to(0);
+        for _ in 0..3 {
+            for host in ["10.0.0.1", "10.0.0.2", "10.0.0.3"] {
+                hll.insert(host);
+            }
+        }
+        assert_that!(hll.estimate()).is_equal_to(3);
+    }
+
+    #[test]
+    fn large_sets_are_within_the_expected_error() {
+        let mut hll = HyperLogLog::new(12);
+        for i in 0..100_000 {
+            hll.insert(&format!("10.{}.{}.{}", i >> 16, (i >> 8) & 255, i & 255));
+        }
+        // Three standard errors of 1.6%.
+        assert_that!(hll.estimate()).is_in_range(95_000..=105_000);
+    }
+}
@@ -1,30 +1,456 @@
-use async_nats::Client;
-use futures_util::StreamExt;
-use std::time::Duration;
-use tokio::sync::mpsc::Sender;
-use tracing::info;
+use async_nats::{Client, HeaderMap};
+use clap::ValueEnum;
+use futures_util::{StreamExt, stream::select_all};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    task::JoinSet,
+    time::interval,
+};
+use tracing::{error, info, warn};
 use tryhard::{RetryFutureConfig, retry_fn};
 
+use crate::{models::SyslogEnvelope, parser::ParseError};
+
+pub mod file;
+pub mod jetstream;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod syslog;
+
+pub const DEAD_LETTER_REASON_HEADER: &str = "Log-Analyzer-Reason";
+pub const DEAD_LETTER_SUBJECT_HEADER: &str = "Log-Analyzer-Subject";
+
+const DEAD_LETTER_BATCH_SIZE: usize = 1_000;
+const DEAD_LETTER_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Input {
+    /// Core NATS subscription to `--subject`
+    Nats,
+    /// Durable JetStream pull consumer on `--subject`
+    #[value(name = "jetstream")]
+    JetStream,
+    /// Follow local files matching `--file`
+    File,
+    /// Syslog listener on `--syslog-udp` and `--syslog-tcp`
+    Syslog,
+    /// Kafka consumer group reading `--kafka-topics`
+    #[cfg(feature = "kafka")]
+    Kafka,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct IngestArgs {
+    /// Where log lines are read from, repeat or comma separate for several
+    #[arg(long, value_enum, value_delimiter = ',', default_value = "nats")]
+    pub input: Vec<Input>,
+
+    #[arg(long, default_value = "nats://127.0.0.1:4222")]
+    pub nats_url: String,
+
+    /// Subjects to read, wildcards allowed, repeat or comma separate for several
+    #[arg(long, value_delimiter = ',', default_value = "logs")]
+    pub subject: Vec<String>,
+
+    /// Share `--subject` with every subscriber in this NATS queue group
+    #[arg(long)]
+    pub queue_group: Option<String>,
+
+    #[command(flatten)]
+    pub jetstream: jetstream::JetStreamArgs,
+
+    #[command(flatten)]
+    pub file: file::FileArgs,
+
+    #[command(flatten)]
+    pub syslog: syslog::SyslogArgs,
+
+    #[cfg(feature = "kafka")]
+    #[command(flatten)]
+    pub kafka: kafka::KafkaArgs,
+}
+
+/// The source logs on `subject` are reported under: the dot separated
+/// `tokens` of the subject joined back together, or the whole subject when
+/// none of them exist.
+pub fn source(subject: &str, tokens: &[usize]) -> String {
+    let parts: Vec<_> = subject.split('.').collect();
+    let picked: Vec<_> = tokens
+        .iter()
+        .filter_map(|&i| parts.get(i).copied())
+        .collect();
+    if picked.is_empty() {
+        subject.to_owned()
+    } else {
+        picked.join(".")
+    }
+}
+
+/// Whether `subject` is delivered to a subscription on `pattern`, where `*`
+/// stands for one token and a trailing `>` for one or more.
+pub fn subject_matches(pattern: &str, subject: &str) -> bool {
+    let mut subject = subject.split('.');
+    for token in pattern.split('.') {
+        match (token, subject.next()) {
+            (">", Some(_)) => return true,
+            ("*", Some(_)) => {}
+            (token, Some(s)) if token == s => {}
+            _ => return false,
+        }
+    }
+    subject.next().is_none()
+}
+
+/// Newline separated log lines together with the subject they arrived on.
+#[derive(Debug)]
+pub struct Chunk {
+    pub subject: String,
+    pub payload: String,
+    /// Set when a source with delivery guarantees is sending this again.
+    pub redelivered: bool,
+    /// Header of the syslog message `payload` was the body of.
+    pub syslog: Option<SyslogEnvelope>,
+    /// Fired once every line of `payload` has been applied to `Analytics`.
+    pub ack: Option<Ack>,
+}
+
+/// Lets sources with delivery guarantees commit or acknowledge a chunk only
+/// after it has been aggregated.
+#[derive(Debug)]
+pub struct Ack(oneshot::Sender<()>);
+
+impl Ack {
+    pub fn new() -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        (Self(tx), rx)
+    }
+
+    pub fn ack(self) {
+        self.0.send(()).ok();
+    }
+}
+
+/// A line the worker's parser rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeadLetter {
+    pub subject: String,
+    pub reason: ParseError,
+    pub line: String,
+}
+
+/// Where rejected lines get republished, see `--dead-letter-subject`.
+#[derive(Debug)]
+pub struct DeadLetters {
+    pub subject: String,
+    pub rx: Receiver<DeadLetter>,
+}
+
+/// Runs every configured input until they have all stopped.
+pub async fn run(args: IngestArgs, tx: Sender<Chunk>, mut dead_letters: Option<DeadLetters>) {
+    let IngestArgs {
+        mut input,
+        nats_url,
+        subject,
+        queue_group,
+        jetstream,
+        file,
+        syslog,
+        #[cfg(feature = "kafka")]
+        kafka,
+    } = args;
+    // Each input runs once however often it is listed.
+    let mut seen = HashSet::new();
+    input.retain(|input| seen.insert(*input));
+    let mut jetstream = Some(jetstream);
+    let mut file = Some(file);
+    let mut syslog = Some(syslog);
+    #[cfg(feature = "kafka")]
+    let mut kafka = Some(kafka);
+    let mut inputs = JoinSet::new();
+    for input in input {
+        let tx = tx.clone();
+        match input {
+            Input::Nats => {
+                let (nats_url, subject) = (nats_url.clone(), subject.clone());
+                let queue_group = queue_group.clone();
+                let dead_letters = dead_letters.take();
+                inputs.spawn(async move {
+                    if let Err(e) =
+                        consume_nats(nats_url, subject, queue_group, tx, dead_letters).await
+                    {
+                        error!("NATS ingest error: {e}");
+                    }
+                });
+            }
+            Input::JetStream => {
+                let Some(jetstream) = jetstream.take() else {
+                    continue;
+                };
+                let (nats_url, subject) = (nats_url.clone(), subject.clone());
+                let dead_letters = dead_letters.take();
+                inputs.spawn(async move {
+                    if let Err(e) =
+                        jetstream::consume_jetstream(nats_url, subject, jetstream, tx, dead_letters)
+                            .await
+                    {
+                        error!("JetStream ingest error: {e}");
+                    }
+                });
+            }
+            Input::File => {
+                let Some(file) = file.take() else { continue };
+                inputs.spawn(async move {
+                    if let Err(e) = file::consume_files(file, tx).await {
+                        error!("File ingest error: {e}");
+                    }
+                });
+            }
+            Input::Syslog => {
+                let Some(syslog) = syslog.take() else {
+                    continue;
+                };
+                inputs.spawn(async move {
+                    if let Err(e) = syslog::consume_syslog(syslog, tx).await {
+                        error!("Syslog ingest error: {e}");
+                    }
+                });
+            }
+            #[cfg(feature = "kafka")]
+            Input::Kafka => {
+                let Some(kafka) = kafka.take() else { continue };
+                inputs.spawn(async move {
+                    if let Err(e) = kafka::consume_kafka(kafka, tx).await {
+                        error!("Kafka ingest error: {e}");
+                    }
+                });
+            }
+        }
+    }
+    // Dropping the unused receiver lets the workers see the channel close.
+    if dead_letters.take().is_some() {
+        warn!("--dead-letter-subject needs a NATS input, rejected lines will not be republished");
+    }
+    inputs.join_all().await;
+}
+
 pub async fn consume_nats(
     nats_url: String,
-    subject: String,
-    tx: Sender<String>,
+    subjects: Vec<String>,
+    queue_group: Option<String>,
+    tx: Sender<Chunk>,
+    dead_letters: Option<DeadLetters>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    let client = connect_nats(&nats_url).await?;
+    if let Some(dead_letters) = dead_letters {
+        tokio::spawn(publish_dead_letters(client.clone(), dead_letters));
+    }
+    let mut subscriptions = Vec::with_capacity(subjects.len());
+    for subject in subjects {
+        subscriptions.push(match &queue_group {
+            Some(queue_group) => {
+                info!("Joining queue group {queue_group} on {subject}");
+                client.queue_subscribe(subject, queue_group.clone()).await?
+            }
+            None => client.subscribe(subject).await?,
+        });
+    }
+    let mut sub = select_all(subscriptions);
+    while let Some(msg) = sub.next().await {
+        let chunk = Chunk {
+            subject: msg.subject.to_string(),
+            payload: String::from_utf8_lossy(&msg.payload).to_string(),
+            redelivered: false,
+            syslog: None,
+            ack: None,
+        };
+        if tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn connect_nats(nats_url: &str) -> Result<Client, async_nats::ConnectError> {
     let config = RetryFutureConfig::new(10)
         .exponential_backoff(Duration::from_millis(100))
         .max_delay(Duration::from_secs(5));
-    let client: Client = retry_fn(|| async {
+    retry_fn(|| async {
         info!("Attempting to connect to NATS at {nats_url}");
-        async_nats::connect(&nats_url).await
+        async_nats::connect(nats_url).await
     })
     .with_config(config)
-    .await?;
-    let mut sub = client.subscribe(subject.clone()).await?;
-    while let Some(msg) = sub.next().await {
-        let payload = String::from_utf8_lossy(&msg.payload).to_string();
-        if tx.send(payload).await.is_err() {
-            break;
+    .await
+}
+
+/// Republishes rejected lines, one message per original subject and reason
+/// so the headers describe every line in the payload.
+async fn publish_dead_letters(client: Client, DeadLetters { subject, mut rx }: DeadLetters) {
+    let mut batcher = DeadLetterBatcher::default();
+    let mut flush = interval(DEAD_LETTER_FLUSH_INTERVAL);
+    loop {
+        tokio::select! {
+            maybe_letter = rx.recv() => match maybe_letter {
+                Some(letter) => {
+                    if let Some(batch) = batcher.push(letter) {
+                        publish_dead_letter_batch(&client, &subject, batch).await;
+                    }
+                }
+                None => break,
+            },
+            _ = flush.tick() => {
+                for batch in batcher.drain() {
+                    publish_dead_letter_batch(&client, &subject, batch).await;
+                }
+            }
         }
     }
-    Ok(())
+    for batch in batcher.drain() {
+        publish_dead_letter_batch(&client, &subject, batch).await;
+    }
+}
+
+async fn publish_dead_letter_batch(client: &Client, subject: &str, batch: DeadLetterBatch) {
+    let mut headers = HeaderMap::new();
+    headers.insert(DEAD_LETTER_REASON_HEADER, batch.reason.to_string().as_str());
+    headers.insert(DEAD_LETTER_SUBJECT_HEADER, batch.subject.as_str());
+    if let Err(e) = client
+        .publish_with_headers(subject.to_string(), headers, batch.lines.into())
+        .await
+    {
+        warn!("Failed to publish dead letters to {subject}: {e}");
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct DeadLetterBatch {
+    subject: String,
+    reason: ParseError,
+    lines: String,
+}
+
+#[derive(Debug, Default)]
+struct DeadLetterBatcher {
+    pending: HashMap<(String, ParseError), (usize, String)>,
+}
+
+impl DeadLetterBatcher {
+    /// Buffers `letter`, handing back its batch once it is full.
+    fn push(
+        &mut self,
+        DeadLetter {
+            subject,
+            reason,
+            line,
+        }: DeadLetter,
+    ) -> Option<DeadLetterBatch> {
+        let key = (subject, reason);
+        let (count, lines) = self.pending.entry(key.clone()).or_default();
+        lines.push_str(&line);
+        lines.push('\n');
+        *count += 1;
+        if *count < DEAD_LETTER_BATCH_SIZE {
+            return None;
+        }
+        let (_, lines) = self.pending.remove(&key)?;
+        Some(DeadLetterBatch {
+            subject: key.0,
+            reason: key.1,
+            lines,
+        })
+    }
+
+    fn drain(&mut self) -> Vec<DeadLetterBatch> {
+        self.pending
+            .drain()
+            .map(|((subject, reason), (_, lines))| DeadLetterBatch {
+                subject,
+                reason,
+                lines,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    fn letter(subject: &str, reason: ParseError, line: &str) -> DeadLetter {
+        DeadLetter {
+            subject: subject.into(),
+            reason,
+            line: line.into(),
+        }
+    }
+
+    #[test]
+    fn source_picks_subject_tokens() {
+        assert_that!(source("logs.web.prod", &[])).is_equal_to("logs.web.prod".to_owned());
+        assert_that!(source("logs.web.prod", &[1])).is_equal_to("web".to_owned());
+        assert_that!(source("logs.web.prod", &[1, 2])).is_equal_to("web.prod".to_owned());
+        assert_that!(source("logs", &[1])).is_equal_to("logs".to_owned());
+    }
+
+    #[test]
+    fn subject_matches_nats_wildcards() {
+        assert!(subject_matches("logs", "logs"));
+        assert!(subject_matches("logs.>", "logs.rejected"));
+        assert!(subject_matches("logs.*.prod", "logs.web.prod"));
+        assert!(subject_matches("*.rejected", "logs.rejected"));
+        assert!(!subject_matches("logs.>", "logs"));
+        assert!(!subject_matches("logs.*", "logs.web.prod"));
+        assert!(!subject_matches("logs", "logs.rejected"));
+        assert!(!subject_matches("logs.web", "logs.rejected"));
+    }
+
+    #[test]
+    fn dead_letters_are_grouped_by_subject_and_reason() {
+        let mut batcher = DeadLetterBatcher::default();
+        assert_that!(batcher.push(letter("logs", ParseError::Status, "a"))).is_none();
+        assert_that!(batcher.push(letter("logs", ParseError::Status, "b"))).is_none();
+        assert_that!(batcher.push(letter("logs", ParseError::Bytes, "c"))).is_none();
+        assert_that!(batcher.push(letter("other", ParseError::Status, "d"))).is_none();
+
+        let mut batches = batcher.drain();
+        batches.sort_unstable_by(|a, b| (&a.subject, &a.lines).cmp(&(&b.subject, &b.lines)));
+        assert_that!(batches).is_equal_to(vec![
+            DeadLetterBatch {
+                subject: "logs".into(),
+                reason: ParseError::Status,
+                lines: "a\nb\n".into(),
+            },
+            DeadLetterBatch {
+                subject: "logs".into(),
+                reason: ParseError::Bytes,
+                lines: "c\n".into(),
+            },
+            DeadLetterBatch {
+                subject: "other".into(),
+                reason: ParseError::Status,
+                lines: "d\n".into(),
+            },
+        ]);
+        assert_that!(batcher.drain()).is_empty();
+    }
+
+    #[test]
+    fn full_dead_letter_batches_are_released() {
+        let mut batcher = DeadLetterBatcher::default();
+        for _ in 1..DEAD_LETTER_BATCH_SIZE {
+            assert_that!(batcher.push(letter("logs", ParseError::Structure, "x"))).is_none();
+        }
+        let batch = batcher
+            .push(letter("logs", ParseError::Structure, "x"))
+            .unwrap();
+        assert_that!(batch.lines.lines().count()).is_equal_to(DEAD_LETTER_BATCH_SIZE);
+        assert_that!(batcher.drain()).is_empty();
+    }
 }
@@ -0,0 +1,440 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::Metadata,
+    io::SeekFrom,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt},
+    sync::mpsc::{self, Sender},
+    time::interval,
+};
+use tracing::{info, warn};
+
+use super::{Ack, Chunk};
+
+/// Upper bound on the bytes read from one file into a single chunk.
+const MAX_CHUNK_BYTES: u64 = 1 << 20;
+
+#[derive(clap::Args, Debug)]
+pub struct FileArgs {
+    /// Files or globs to follow, repeat or comma separate for several
+    #[arg(long = "file", value_delimiter = ',')]
+    pub files: Vec<String>,
+
+    /// Where read offsets are kept across restarts
+    #[arg(long, default_value = "file-offsets.json")]
+    pub file_checkpoint: PathBuf,
+
+    /// How often followed files are checked for new lines, in milliseconds
+    #[arg(long, default_value_t = 500)]
+    pub file_poll_ms: u64,
+}
+
+/// Identifies a file independently of its name, so a rotated file is still
+/// recognised after it has been renamed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct FileId {
+    dev: u64,
+    ino: u64,
+}
+
+impl FileId {
+    #[cfg(unix)]
+    fn of(meta: &Metadata, _path: &Path) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            dev: meta.dev(),
+            ino: meta.ino(),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn of(_meta: &Metadata, path: &Path) -> Self {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        Self {
+            dev: 0,
+            ino: hasher.finish(),
+        }
+    }
+}
+
+/// How far into a file every line has been aggregated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Position {
+    #[serde(flatten)]
+    id: FileId,
+    path: PathBuf,
+    offset: u64,
+    /// How many times the file was truncated while followed, so offsets
+    /// from before a truncation never overwrite those after it.
+    #[serde(skip)]
+    generation: u64,
+}
+
+/// Records `position` unless an ack for a later read of the same file got
+/// in first, acks arrive in whatever order their chunks were aggregated.
+fn advance(positions: &mut HashMap<FileId, Position>, position: Position) -> bool {
+    if let Some(known) = positions.get(&position.id)
+        && (known.generation, known.offset) >= (position.generation, position.offset)
+    {
+        return false;
+    }
+    positions.insert(position.id, position);
+    true
+}
+
+/// Follows every file matching `--file`, forwarding complete lines as
+/// [`Chunk`]s whose subject is the file's path.
+///
+/// Offsets are written to `--file-checkpoint` only once a chunk has been
+/// applied to `Analytics`, so a restart resumes after the last aggregated
+/// line. Renamed files are read to the end before being let go and
+/// truncated files are read again from the start.
+pub async fn consume_files(
+    FileArgs {
+        files,
+        file_checkpoint,
+        file_poll_ms,
+    }: FileArgs,
+    tx: Sender<Chunk>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut positions = load_checkpoint(&file_checkpoint).await?;
+    let mut tailer = Tailer::new(
+        files,
+        positions.values().map(|p| (p.id, p.offset)).collect(),
+    );
+    let (acked_tx, mut acked_rx) = mpsc::unbounded_channel();
+    let mut poll = interval(Duration::from_millis(file_poll_ms));
+    let mut dirty = false;
+    info!("Following files, checkpointing to {file_checkpoint:?}");
+    loop {
+        tokio::select! {
+            _ = poll.tick() => {
+                let reads = tailer.poll().await;
+                if !reads.is_empty() {
+                    // Keep reading while there is a backlog.
+                    poll.reset_immediately();
+                }
+                for read in reads {
+                    let (ack, acked) = Ack::new();
+                    let chunk = Chunk {
+                        subject: read.path.display().to_string(),
+                        payload: read.payload,
+                        redelivered: false,
+                        syslog: None,
+                        ack: Some(ack),
+                    };
+                    let acked_tx = acked_tx.clone();
+                    let position = Position {
+                        id: read.id,
+                        path: read.path,
+                        offset: read.offset,
+                        generation: read.generation,
+                    };
+                    tokio::spawn(async move {
+                        if acked.await.is_ok() {
+                            acked_tx.send(position).ok();
+                        }
+                    });
+                    if tx.send(chunk).await.is_err() {
+                        return Ok(());
+                    }
+                }
+                if dirty {
+                    positions.retain(|id, _| tailer.follows(id));
+                    if let Err(e) = save_checkpoint(&file_checkpoint, &positions).await {
+                        warn!("Failed to write {file_checkpoint:?}: {e}");
+                    }
+                    dirty = false;
+                }
+            }
+            Some(position) = acked_rx.recv() => {
+                dirty |= advance(&mut positions, position);
+            }
+        }
+    }
+}
+
+async fn load_checkpoint(path: &Path) -> std::io::Result<HashMap<FileId, Position>> {
+    let json = match fs::read_to_string(path).await {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+    let positions: Vec<Position> = serde_json::from_str(&json)?;
+    Ok(positions.into_iter().map(|p| (p.id, p)).collect())
+}
+
+/// Replaces the checkpoint through a rename so a crash never leaves it half
+/// written.
+async fn save_checkpoint(
+    path: &Path,
+    positions: &HashMap<FileId, Position>,
+) -> std::io::Result<()> {
+    let positions: Vec<_> = positions.values().collect();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, serde_json::to_vec_pretty(&positions)?).await?;
+    fs::rename(&tmp, path).await
+}
+
+/// Complete lines read from a file, ending at `offset`.
+#[derive(Debug)]
+struct Read {
+    id: FileId,
+    path: PathBuf,
+    payload: String,
+    offset: u64,
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Followed {
+    path: PathBuf,
+    file: File,
+    offset: u64,
+    /// Bumped every time the file is found truncated.
+    generation: u64,
+}
+
+#[derive(Debug)]
+struct Tailer {
+    patterns: Vec<String>,
+    followed: HashMap<FileId, Followed>,
+    resume: HashMap<FileId, u64>,
+}
+
+impl Tailer {
+    fn new(patterns: Vec<String>, resume: HashMap<FileId, u64>) -> Self {
+        Self {
+            patterns,
+            followed: HashMap::new(),
+            resume,
+        }
+    }
+
+    fn follows(&self, id: &FileId) -> bool {
+        self.followed.contains_key(id)
+    }
+
+    /// Reads up to a chunk of what was appended to every file since the
+    /// last poll.
+    async fn poll(&mut self) -> Vec<Read> {
+        let mut matched = HashSet::new();
+        for path in self.matching_paths() {
+            let Ok(meta) = fs::metadata(&path).await else {
+                continue;
+            };
+            if !meta.is_file() {
+                continue;
+            }
+            let id = FileId::of(&meta, &path);
+            matched.insert(id);
+            if let Some(followed) = self.followed.get_mut(&id) {
+                if followed.path != path {
+                    info!("{:?} was renamed to {path:?}", followed.path);
+                    followed.path = path;
+                }
+                continue;
+            }
+            match File::open(&path).await {
+                Ok(file) => {
+                    let offset = self.resume.remove(&id).unwrap_or_default();
+                    info!("Following {path:?} from offset {offset}");
+                    self.followed.insert(
+                        id,
+                        Followed {
+                            path,
+                            file,
+                            offset,
+                            generation: 0,
+                        },
+                    );
+                }
+                Err(e) => warn!("Failed to open {path:?}: {e}"),
+            }
+        }
+
+        let mut reads = Vec::new();
+        for (id, followed) in self.followed.iter_mut() {
+            match followed.read_lines().await {
+                Ok(Some(payload)) => reads.push(Read {
+                    id: *id,
+                    path: followed.path.clone(),
+                    payload,
+                    offset: followed.offset,
+                    generation: followed.generation,
+                }),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to read {:?}: {e}", followed.path),
+            }
+        }
+        // Rotated away or deleted files are let go once read to the end.
+        self.followed
+            .retain(|id, _| matched.contains(id) || reads.iter().any(|r| r.id == *id));
+        reads
+    }
+
+    fn matching_paths(&self) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        for pattern in &self.patterns {
+            match glob::glob(pattern) {
+                Ok(matches) => paths.extend(matches.flatten()),
+                Err(e) => warn!("Invalid file pattern {pattern}: {e}"),
+            }
+        }
+        paths
+    }
+}
+
+impl Followed {
+    /// The next run of complete lines, or `None` once only a partial line
+    /// is left.
+    async fn read_lines(&mut self) -> std::io::Result<Option<String>> {
+        let len = self.file.metadata().await?.len();
+        if len < self.offset {
+            info!("{:?} was truncated, reading from the start", self.path);
+            self.offset = 0;
+            self.generation += 1;
+        }
+        if len == self.offset {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(self.offset)).await?;
+        let mut buf = Vec::new();
+        (&mut self.file)
+            .take(MAX_CHUNK_BYTES)
+            .read_to_end(&mut buf)
+            .await?;
+        let end = match buf.iter().rposition(|&b| b == b'\n') {
+            Some(newline) => newline + 1,
+            // A single line longer than a chunk is passed on as it is.
+            None if buf.len() as u64 == MAX_CHUNK_BYTES => buf.len(),
+            None => return Ok(None),
+        };
+        buf.truncate(end);
+        self.offset += end as u64;
+        Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use std::{fs::OpenOptions, io::Write};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("log-analyzer-{name}-{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn append(path: &Path, text: &str) {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(text.as_bytes())
+            .unwrap();
+    }
+
+    async fn payloads(tailer: &mut Tailer) -> Vec<String> {
+        tailer.poll().await.into_iter().map(|r| r.payload).collect()
+    }
+
+    #[tokio::test]
+    async fn only_complete_lines_are_read() {
+        let dir = scratch_dir("partial");
+        let log = dir.join("access.log");
+        append(&log, "one\ntw");
+        let mut tailer = Tailer::new(
+            vec![dir.join("*.log").display().to_string()],
+            HashMap::new(),
+        );
+
+        assert_that!(payloads(&mut tailer).await).is_equal_to(vec!["one\n".to_owned()]);
+        assert_that!(payloads(&mut tailer).await).is_empty();
+        append(&log, "o\n");
+        assert_that!(payloads(&mut tailer).await).is_equal_to(vec!["two\n".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn renamed_files_are_drained_and_truncated_files_restart() {
+        let dir = scratch_dir("rotate");
+        let log = dir.join("access.log");
+        append(&log, "one\n");
+        let mut tailer = Tailer::new(vec![log.display().to_string()], HashMap::new());
+        assert_that!(payloads(&mut tailer).await).is_equal_to(vec!["one\n".to_owned()]);
+
+        // Rename rotation: the old file gets its last line, the new one starts at 0.
+        append(&log, "two\n");
+        std::fs::rename(&log, dir.join("access.log.1")).unwrap();
+        append(&log, "three\n");
+        let mut rotated = payloads(&mut tailer).await;
+        rotated.sort();
+        assert_that!(rotated).is_equal_to(vec!["three\n".to_owned(), "two\n".to_owned()]);
+        assert_that!(payloads(&mut tailer).await).is_empty();
+        assert_that!(tailer.followed.len()).is_equal_to(1);
+
+        // Copy-truncate rotation.
+        std::fs::write(&log, "").unwrap();
+        append(&log, "four\n");
+        assert_that!(payloads(&mut tailer).await).is_equal_to(vec!["four\n".to_owned()]);
+    }
+
+    #[tokio::test]
+    async fn checkpoints_resume_where_aggregation_stopped() {
+        let dir = scratch_dir("checkpoint");
+        let log = dir.join("access.log");
+        append(&log, "one\ntwo\n");
+        let mut tailer = Tailer::new(vec![log.display().to_string()], HashMap::new());
+        let read = tailer.poll().await.remove(0);
+
+        let checkpoint = dir.join("offsets.json");
+        let position = Position {
+            id: read.id,
+            path: read.path,
+            offset: 4,
+            generation: 0,
+        };
+        save_checkpoint(&checkpoint, &HashMap::from([(read.id, position.clone())]))
+            .await
+            .unwrap();
+        let positions = load_checkpoint(&checkpoint).await.unwrap();
+        assert_that!(positions.get(&read.id)).is_equal_to(Some(&position));
+
+        let resume = positions.values().map(|p| (p.id, p.offset)).collect();
+        let mut tailer = Tailer::new(vec![log.display().to_string()], resume);
+        assert_that!(payloads(&mut tailer).await).is_equal_to(vec!["two\n".to_owned()]);
+    }
+
+    #[test]
+    fn out_of_order_acks_never_move_a_checkpoint_back() {
+        let id = FileId { dev: 1, ino: 2 };
+        let position = |offset, generation| Position {
+            id,
+            path: "access.log".into(),
+            offset,
+            generation,
+        };
+        let mut positions = HashMap::new();
+        assert_that!(advance(&mut positions, position(200, 0))).is_true();
+        assert_that!(advance(&mut positions, position(100, 0))).is_false();
+        assert_that!(positions[&id].offset).is_equal_to(200);
+
+        // After a truncation the offset starts over, late acks from before it
+        // are ignored.
+        assert_that!(advance(&mut positions, position(50, 1))).is_true();
+        assert_that!(advance(&mut positions, position(300, 0))).is_false();
+        assert_that!(positions[&id].offset).is_equal_to(50);
+    }
+}
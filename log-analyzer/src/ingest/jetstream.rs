@@ -0,0 +1,99 @@
+use async_nats::jetstream::{
+    self,
+    consumer::{AckPolicy, PullConsumer, pull},
+    stream,
+};
+use futures_util::StreamExt;
+use tokio::{sync::mpsc::Sender, time::Duration};
+use tracing::{info, warn};
+
+use super::{Ack, Chunk, DeadLetters, connect_nats, publish_dead_letters};
+use crate::worker::FLUSH_INTERVAL;
+
+/// Long enough for a message's lines to wait out a few worker flushes and
+/// the aggregator before being acked, so only lost messages are redelivered.
+const ACK_WAIT: Duration = FLUSH_INTERVAL.saturating_mul(20);
+
+#[derive(clap::Args, Debug)]
+pub struct JetStreamArgs {
+    /// Stream capturing `--subject`, created when missing
+    #[arg(long, default_value = "LOGS")]
+    pub jetstream_stream: String,
+
+    /// Durable pull consumer shared by every analyzer instance
+    #[arg(long, default_value = "log-analyzer")]
+    pub jetstream_consumer: String,
+}
+
+/// Pulls from a durable consumer on `stream`, forwarding every message as a
+/// [`Chunk`].
+///
+/// Messages are acked only once their lines have been applied to
+/// `Analytics`. Anything not acked within the consumer's ack wait, for
+/// example after a crash, is redelivered and flagged so it can be counted.
+pub async fn consume_jetstream(
+    nats_url: String,
+    subjects: Vec<String>,
+    JetStreamArgs {
+        jetstream_stream,
+        jetstream_consumer,
+    }: JetStreamArgs,
+    tx: Sender<Chunk>,
+    dead_letters: Option<DeadLetters>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = connect_nats(&nats_url).await?;
+    if let Some(dead_letters) = dead_letters {
+        tokio::spawn(publish_dead_letters(client.clone(), dead_letters));
+    }
+    let context = jetstream::new(client);
+    let stream = context
+        .get_or_create_stream(stream::Config {
+            name: jetstream_stream.clone(),
+            subjects,
+            ..Default::default()
+        })
+        .await?;
+    let consumer: PullConsumer = stream
+        .get_or_create_consumer(
+            &jetstream_consumer,
+            pull::Config {
+                durable_name: Some(jetstream_consumer.clone()),
+                ack_policy: AckPolicy::Explicit,
+                ack_wait: ACK_WAIT,
+                ..Default::default()
+            },
+        )
+        .await?;
+    info!("Pulling from JetStream consumer {jetstream_stream}/{jetstream_consumer}");
+
+    let mut messages = consumer.messages().await?;
+    while let Some(msg) = messages.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                warn!("JetStream consume error: {e}");
+                continue;
+            }
+        };
+        let redelivered = msg.info().is_ok_and(|info| info.delivered > 1);
+        let (ack, acked) = Ack::new();
+        let chunk = Chunk {
+            subject: msg.subject.to_string(),
+            payload: String::from_utf8_lossy(&msg.payload).to_string(),
+            redelivered,
+            syslog: None,
+            ack: Some(ack),
+        };
+        tokio::spawn(async move {
+            if acked.await.is_ok()
+                && let Err(e) = msg.ack().await
+            {
+                warn!("Failed to ack JetStream message: {e}");
+            }
+        });
+        if tx.send(chunk).await.is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+};
+
+use futures_util::StreamExt;
+use rdkafka::{
+    ClientConfig, Message, Offset, TopicPartitionList,
+    consumer::{Consumer, StreamConsumer},
+};
+use tokio::sync::mpsc::{self, Sender};
+use tracing::{info, warn};
+
+use super::{Ack, Chunk};
+
+#[derive(clap::Args, Debug)]
+pub struct KafkaArgs {
+    #[arg(long, default_value = "127.0.0.1:9092")]
+    pub kafka_brokers: String,
+
+    #[arg(long, default_value = "log-analyzer")]
+    pub kafka_group: String,
+
+    #[arg(long, value_delimiter = ',', default_value = "logs")]
+    pub kafka_topics: Vec<String>,
+}
+
+/// Consumes `topics` as part of `group`, forwarding every record as a
+/// [`Chunk`] whose subject is the topic name.
+///
+/// Offsets are stored only once a record and every earlier record of its
+/// partition have been applied to `Analytics`, and committed in the
+/// background by librdkafka. Workers hand on their records at least every
+/// [`FLUSH_INTERVAL`](crate::worker::FLUSH_INTERVAL), so under steady load offsets still move every few
+/// seconds, and a crash replays at most the records not yet aggregated.
+pub async fn consume_kafka(
+    KafkaArgs {
+        kafka_brokers,
+        kafka_group,
+        kafka_topics,
+    }: KafkaArgs,
+    tx: Sender<Chunk>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    info!("Joining Kafka group {kafka_group} at {kafka_brokers}");
+    let consumer: Arc<StreamConsumer> = Arc::new(
+        ClientConfig::new()
+            .set("bootstrap.servers", &kafka_brokers)
+            .set("group.id", &kafka_group)
+            .set("enable.auto.commit", "true")
+            .set("enable.auto.offset.store", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?,
+    );
+    let topics: Vec<_> = kafka_topics.iter().map(String::as_str).collect();
+    consumer.subscribe(&topics)?;
+
+    let (acked_tx, mut acked_rx) = mpsc::unbounded_channel();
+    let mut in_flight = InFlight::default();
+    let mut stream = consumer.stream();
+    loop {
+        tokio::select! {
+            maybe_msg = stream.next() => {
+                let msg = match maybe_msg {
+                    Some(Ok(msg)) => msg,
+                    Some(Err(e)) => {
+                        warn!("Kafka consume error: {e}");
+                        continue;
+                    }
+                    None => break,
+                };
+                let (ack, acked) = Ack::new();
+                let chunk = Chunk {
+                    subject: msg.topic().to_owned(),
+                    payload: String::from_utf8_lossy(msg.payload().unwrap_or_default())
+                        .to_string(),
+                    redelivered: false,
+                    syslog: None,
+                    ack: Some(ack),
+                };
+                let record = Record {
+                    topic: msg.topic().to_owned(),
+                    partition: msg.partition(),
+                    offset: msg.offset(),
+                };
+                in_flight.start(&record);
+                let acked_tx = acked_tx.clone();
+                tokio::spawn(async move {
+                    if acked.await.is_ok() {
+                        acked_tx.send(record).ok();
+                    }
+                });
+                if tx.send(chunk).await.is_err() {
+                    break;
+                }
+            }
+            Some(record) = acked_rx.recv() => {
+                let Some(next) = in_flight.finish(&record) else {
+                    continue;
+                };
+                let mut offsets = TopicPartitionList::new();
+                offsets.add_partition_offset(
+                    &record.topic,
+                    record.partition,
+                    Offset::Offset(next),
+                )?;
+                if let Err(e) = consumer.store_offsets(&offsets) {
+                    warn!("Failed to store Kafka offsets: {e}");
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Where a record was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Record {
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+/// Offsets handed to the workers but not yet aggregated, per partition.
+/// Records are aggregated in whatever order their chunks finish, so a
+/// partition's offset only moves past records once none before them are
+/// still pending.
+#[derive(Debug, Default)]
+struct InFlight {
+    partitions: HashMap<(String, i32), Pending>,
+}
+
+#[derive(Debug, Default)]
+struct Pending {
+    offsets: BTreeSet<i64>,
+    /// One past the highest offset aggregated so far.
+    done: i64,
+}
+
+impl InFlight {
+    fn start(&mut self, record: &Record) {
+        self.partitions
+            .entry((record.topic.clone(), record.partition))
+            .or_default()
+            .offsets
+            .insert(record.offset);
+    }
+
+    /// Marks `record` aggregated, returning the offset to store for its
+    /// partition when nothing before it is still pending.
+    fn finish(&mut self, record: &Record) -> Option<i64> {
+        let pending = self
+            .partitions
+            .get_mut(&(record.topic.clone(), record.partition))?;
+        if !pending.offsets.remove(&record.offset) {
+            return None;
+        }
+        pending.done = pending.done.max(record.offset + 1);
+        match pending.offsets.first() {
+            Some(first) if *first < record.offset => None,
+            Some(first) => Some(*first),
+            None => Some(pending.done),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    fn record(partition: i32, offset: i64) -> Record {
+        Record {
+            topic: "logs".into(),
+            partition,
+            offset,
+        }
+    }
+
+    #[test]
+    fn offsets_only_move_past_records_with_nothing_pending_before_them() {
+        let mut in_flight = InFlight::default();
+        for offset in 10..13 {
+            in_flight.start(&record(0, offset));
+        }
+        in_flight.start(&record(1, 5));
+
+        assert_that!(in_flight.finish(&record(0, 11))).is_none();
+        assert_that!(in_flight.finish(&record(1, 5))).is_equal_to(Some(6));
+        assert_that!(in_flight.finish(&record(0, 10))).is_equal_to(Some(12));
+        assert_that!(in_flight.finish(&record(0, 12))).is_equal_to(Some(13));
+    }
+}
@@ -0,0 +1,321 @@
+use std::net::SocketAddr;
+
+use chrono::{DateTime, Datelike, NaiveDateTime, TimeDelta, Utc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+    net::{TcpListener, UdpSocket},
+    sync::mpsc::Sender,
+};
+use tracing::{info, warn};
+
+use super::Chunk;
+use crate::models::{Severity, SyslogEnvelope};
+
+/// Largest message accepted, both as a datagram and as a TCP frame.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+/// Longest octet count read, with its trailing space, before giving up.
+const MAX_OCTET_COUNT_LEN: u64 = 8;
+const SUBJECT: &str = "syslog";
+
+#[derive(clap::Args, Debug)]
+pub struct SyslogArgs {
+    /// UDP address syslog datagrams are received on
+    #[arg(long, default_value = "0.0.0.0:5514")]
+    pub syslog_udp: SocketAddr,
+
+    /// TCP address syslog streams are accepted on, newline delimited or
+    /// octet counted
+    #[arg(long, default_value = "0.0.0.0:5514")]
+    pub syslog_tcp: SocketAddr,
+}
+
+/// Receives RFC 5424 and RFC 3164 messages over UDP and TCP. Each message
+/// becomes a [`Chunk`] holding its body, with the parsed header alongside.
+pub async fn consume_syslog(
+    SyslogArgs {
+        syslog_udp,
+        syslog_tcp,
+    }: SyslogArgs,
+    tx: Sender<Chunk>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let udp = UdpSocket::bind(syslog_udp).await?;
+    let tcp = TcpListener::bind(syslog_tcp).await?;
+    info!("Receiving syslog on udp://{syslog_udp} and tcp://{syslog_tcp}");
+
+    let udp_tx = tx.clone();
+    let udp_task = tokio::spawn(async move {
+        let mut buf = vec![0; MAX_MESSAGE_LEN];
+        loop {
+            let len = match udp.recv_from(&mut buf).await {
+                Ok((len, _)) => len,
+                Err(e) => {
+                    warn!("Syslog UDP receive error: {e}");
+                    continue;
+                }
+            };
+            if !forward(&buf[..len], &udp_tx).await {
+                break;
+            }
+        }
+    });
+
+    loop {
+        tokio::select! {
+            accepted = tcp.accept() => match accepted {
+                Ok((stream, peer)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = read_stream(stream, &tx).await {
+                            warn!("Syslog TCP connection from {peer} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) => warn!("Syslog TCP accept error: {e}"),
+            },
+            _ = tx.closed() => break,
+        }
+    }
+    udp_task.abort();
+    Ok(())
+}
+
+/// Reads frames until the peer hangs up. RFC 6587 octet counting is used
+/// whenever a frame starts with a digit, newline framing otherwise.
+async fn read_stream(stream: impl AsyncRead + Unpin, tx: &Sender<Chunk>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut frame = Vec::new();
+    loop {
+        frame.clear();
+        let octet_counted = match reader.fill_buf().await?.first() {
+            None => return Ok(()),
+            Some(b) => b.is_ascii_digit(),
+        };
+        if octet_counted {
+            (&mut reader)
+                .take(MAX_OCTET_COUNT_LEN)
+                .read_until(b' ', &mut frame)
+                .await?;
+            let len: usize = std::str::from_utf8(&frame)
+                .ok()
+                .and_then(|len| len.trim_end().parse().ok())
+                .filter(|len| *len <= MAX_MESSAGE_LEN)
+                .ok_or_else(|| std::io::Error::other("invalid octet count"))?;
+            frame.resize(len, 0);
+            reader.read_exact(&mut frame).await?;
+        } else {
+            (&mut reader)
+                .take(MAX_MESSAGE_LEN as u64)
+                .read_until(b'\n', &mut frame)
+                .await?;
+        }
+        if !forward(&frame, tx).await {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends one message on, returning `false` once nobody is listening.
+async fn forward(message: &[u8], tx: &Sender<Chunk>) -> bool {
+    let message = String::from_utf8_lossy(message);
+    let message = message.trim_end_matches(['\r', '\n', '\0']);
+    if message.is_empty() {
+        return true;
+    }
+    let (envelope, body) = match parse(message) {
+        Some((envelope, body)) => (Some(envelope), body),
+        None => (None, message),
+    };
+    let chunk = Chunk {
+        subject: SUBJECT.to_owned(),
+        // Line parsers expect one record per line.
+        payload: body.replace(['\r', '\n'], " "),
+        redelivered: false,
+        syslog: envelope,
+        ack: None,
+    };
+    tx.send(chunk).await.is_ok()
+}
+
+/// Splits a syslog message into its header and body, trying RFC 5424 first.
+pub fn parse(message: &str) -> Option<(SyslogEnvelope, &str)> {
+    let rest = message.strip_prefix('<')?;
+    let (pri, rest) = rest.split_once('>')?;
+    if pri.is_empty() || pri.len() > 3 {
+        return None;
+    }
+    let pri: u8 = pri.parse().ok().filter(|pri| *pri <= 191)?;
+    let envelope = SyslogEnvelope {
+        facility: pri >> 3,
+        severity: Severity::from_code(pri & 7)?,
+        timestamp: None,
+        hostname: None,
+        app_name: None,
+    };
+    match rest.strip_prefix("1 ") {
+        Some(rest) => parse_rfc5424(envelope, rest),
+        None => Some(parse_rfc3164(envelope, rest)),
+    }
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+fn parse_rfc5424(mut envelope: SyslogEnvelope, rest: &str) -> Option<(SyslogEnvelope, &str)> {
+    let mut fields = rest.splitn(6, ' ');
+    let timestamp = nil_as_none(fields.next()?);
+    envelope.hostname = nil_as_none(fields.next()?).map(str::to_owned);
+    envelope.app_name = nil_as_none(fields.next()?).map(str::to_owned);
+    let _procid = fields.next()?;
+    let _msgid = fields.next()?;
+    envelope.timestamp = match timestamp {
+        Some(ts) => Some(DateTime::parse_from_rfc3339(ts).ok()?.with_timezone(&Utc)),
+        None => None,
+    };
+    let body = skip_structured_data(fields.next().unwrap_or_default())?;
+    Some((envelope, body.trim_start_matches('\u{feff}')))
+}
+
+/// Returns what follows STRUCTURED-DATA, which is either `-` or a run of
+/// `[...]` elements whose values may contain escaped `]`.
+fn skip_structured_data(rest: &str) -> Option<&str> {
+    if let Some(rest) = rest.strip_prefix('-') {
+        return Some(rest.strip_prefix(' ').unwrap_or(rest));
+    }
+    let mut end = None;
+    let mut in_element = false;
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        if !in_element {
+            if c != '[' {
+                break;
+            }
+            in_element = true;
+        } else if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == ']' {
+            in_element = false;
+            end = Some(i + 1);
+        }
+    }
+    if in_element {
+        return None;
+    }
+    let rest = &rest[end?..];
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+/// `Mmm dd hh:mm:ss HOSTNAME TAG: MSG`, where everything after the priority
+/// is optional in practice.
+fn parse_rfc3164(mut envelope: SyslogEnvelope, rest: &str) -> (SyslogEnvelope, &str) {
+    let mut rest = rest;
+    if let Some(timestamp) = rest
+        .get(..15)
+        .and_then(|timestamp| parse_bsd_timestamp(timestamp, Utc::now()))
+    {
+        envelope.timestamp = Some(timestamp);
+        rest = rest[15..].trim_start();
+        if let Some((hostname, after)) = rest.split_once(' ') {
+            envelope.hostname = Some(hostname.to_owned());
+            rest = after;
+        }
+    }
+    if let Some((tag, body)) = rest.split_once(": ")
+        && !tag.is_empty()
+        && !tag.contains(' ')
+    {
+        let app_name = tag.split_once('[').map_or(tag, |(app_name, _)| app_name);
+        envelope.app_name = Some(app_name.to_owned());
+        rest = body;
+    }
+    (envelope, rest)
+}
+
+/// BSD timestamps carry no year. The one of `now` is assumed, unless that
+/// puts the timestamp more than a day ahead, as for a message sent on 31
+/// December and received on 1 January, in which case it is the year before.
+fn parse_bsd_timestamp(timestamp: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let in_year = |year: i32| {
+        NaiveDateTime::parse_from_str(&format!("{year} {timestamp}"), "%Y %b %e %H:%M:%S")
+            .ok()
+            .map(|ts| ts.and_utc())
+    };
+    match in_year(now.year())? {
+        ts if ts - now > TimeDelta::days(1) => in_year(now.year() - 1),
+        ts => Some(ts),
+    }
+}
+
+fn nil_as_none(field: &str) -> Option<&str> {
+    (field != "-").then_some(field)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_rfc5424() {
+        let message = r#"<165>1 2003-10-11T22:14:15.003Z mymachine.example.com evntslog - ID47 [exampleSDID@32473 iut="3" eventID="1011\]"] 127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#;
+        let (envelope, body) = parse(message).unwrap();
+        assert_that!(envelope).is_equal_to(SyslogEnvelope {
+            facility: 20,
+            severity: Severity::Notice,
+            timestamp: Some(
+                Utc.with_ymd_and_hms(2003, 10, 11, 22, 14, 15).unwrap()
+                    + chrono::Duration::milliseconds(3),
+            ),
+            hostname: Some("mymachine.example.com".into()),
+            app_name: Some("evntslog".into()),
+        });
+        assert_that!(body).is_equal_to(
+            r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#,
+        );
+    }
+
+    #[test]
+    fn parses_rfc5424_without_structured_data() {
+        let (envelope, body) = parse("<11>1 - host - - - - disk full").unwrap();
+        assert_that!(envelope.severity).is_equal_to(Severity::Error);
+        assert_that!(envelope.timestamp).is_none();
+        assert_that!(envelope.app_name).is_none();
+        assert_that!(body).is_equal_to("disk full");
+    }
+
+    #[test]
+    fn parses_rfc3164() {
+        let (envelope, body) =
+            parse("<34>Oct  1 22:14:15 mymachine su[231]: 'su root' failed for lonvick").unwrap();
+        assert_that!(envelope.facility).is_equal_to(4);
+        assert_that!(envelope.severity).is_equal_to(Severity::Critical);
+        assert_that!(envelope.hostname).is_equal_to(Some("mymachine".to_owned()));
+        assert_that!(envelope.app_name).is_equal_to(Some("su".to_owned()));
+        assert_that!(envelope.timestamp.map(|ts| (ts.month(), ts.day())))
+            .is_equal_to(Some((10, 1)));
+        assert_that!(body).is_equal_to("'su root' failed for lonvick");
+    }
+
+    #[test]
+    fn bsd_timestamps_from_late_last_year_roll_back() {
+        let new_year = Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 5).unwrap();
+        assert_that!(parse_bsd_timestamp("Dec 31 23:59:50", new_year)).is_equal_to(Some(
+            Utc.with_ymd_and_hms(2025, 12, 31, 23, 59, 50).unwrap(),
+        ));
+        assert_that!(parse_bsd_timestamp("Jan  1 00:00:10", new_year))
+            .is_equal_to(Some(Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 10).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn octet_counts_without_a_space_are_rejected() {
+        let (tx, _rx) = tokio::sync::mpsc::channel(1);
+        let stream = "1".repeat(MAX_MESSAGE_LEN);
+        assert_that!(read_stream(stream.as_bytes(), &tx).await).is_err();
+    }
+
+    #[test]
+    fn rejects_messages_without_priority() {
+        assert_that!(parse("just a line")).is_none();
+        assert_that!(parse("<999>1 - - - - - - x")).is_none();
+    }
+}
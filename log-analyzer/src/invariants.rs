@@ -1,9 +1,9 @@
 use std::str::FromStr;
 
-use chrono::{DateTime, Datelike, TimeZone, Timelike, Utc};
+use chrono::{DateTime, Datelike, TimeDelta, TimeZone, Timelike, Utc};
 use derive_more::{AsRef, Debug, Display};
 
-#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Hostname(String);
 
 impl Hostname {
@@ -20,7 +20,7 @@ impl FromStr for Hostname {
     }
 }
 
-#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Endpoint(String);
 
 impl FromStr for Endpoint {
@@ -31,6 +31,75 @@ impl FromStr for Endpoint {
     }
 }
 
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Referrer(String);
+
+impl FromStr for Referrer {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(200).collect()))
+    }
+}
+
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Service(String);
+
+impl FromStr for Service {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(100).collect()))
+    }
+}
+
+/// Syslog APP-NAME, at most 48 characters as in RFC 5424.
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AppName(String);
+
+impl FromStr for AppName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(48).collect()))
+    }
+}
+
+/// Log level, normalised to upper case so `warn` and `WARN` share a bucket.
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Level(String);
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(20).collect::<String>().to_uppercase()))
+    }
+}
+
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Message(String);
+
+impl FromStr for Message {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(200).collect()))
+    }
+}
+
+/// Value of an extra named capture from a user defined pattern.
+#[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExtraValue(String);
+
+impl FromStr for ExtraValue {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Self(s.chars().take(100).collect()))
+    }
+}
+
 #[derive(Debug, Display, AsRef, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Copy)]
 pub struct Timestamp(DateTime<Utc>);
 
@@ -38,6 +107,13 @@ impl Timestamp {
     pub fn into_utc(self) -> DateTime<Utc> {
         self.0
     }
+    /// The start of the `width` wide bucket holding `at`, buckets being
+    /// aligned to the Unix epoch.
+    pub fn bucket(at: DateTime<Utc>, width: TimeDelta) -> Self {
+        let width = width.num_seconds().max(1);
+        let start = at.timestamp().div_euclid(width) * width;
+        Self(DateTime::from_timestamp(start, 0).unwrap_or(at))
+    }
 }
 
 impl From<DateTime<Utc>> for Timestamp {
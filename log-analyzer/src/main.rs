@@ -1,15 +1,26 @@
 mod analytics;
+mod analyze;
+mod capped;
+mod histogram;
+mod hyperloglog;
 mod ingest;
 mod invariants;
 mod metrics_server;
 mod models;
+mod parser;
+mod pattern;
 mod prometheus;
+mod report;
+mod space_saving;
+mod timeseries;
 mod worker;
 
-use analytics::Analytics;
-use clap::Parser;
-use ingest::consume_nats;
-use std::{fs::File, sync::Arc};
+use analytics::{LimitArgs, Sources};
+use clap::{CommandFactory, Parser, Subcommand, error::ErrorKind};
+use ingest::{Chunk, DeadLetter, DeadLetters, IngestArgs};
+use metrics_server::HttpIngest;
+use parser::{LogFormat, LogParser};
+use std::{fs::File, path::PathBuf, sync::Arc};
 use tokio::{
     sync::mpsc::{self, Receiver, Sender},
     task::{JoinError, JoinHandle},
@@ -17,7 +28,7 @@ use tokio::{
 };
 use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter};
-use worker::{Metric, worker_loop};
+use worker::{Batch, Metric, worker_loop};
 
 #[cfg(feature = "pprof")]
 use pprof::ProfilerGuard;
@@ -25,15 +36,44 @@ use pprof::ProfilerGuard;
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    #[arg(long, default_value = "nats://127.0.0.1:4222")]
-    nats_url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    #[arg(long, default_value = "logs")]
-    subject: String,
+    #[command(flatten)]
+    ingest: IngestArgs,
+
+    #[command(flatten)]
+    limits: LimitArgs,
+
+    /// Dot separated subject tokens, counted from 0, that name the source
+    /// metrics are labelled with. For `logs.<service>.<env>` use `1` or
+    /// `1,2`. The whole subject is used when unset.
+    #[arg(long, value_delimiter = ',')]
+    source_tokens: Vec<usize>,
+
+    #[arg(long, value_enum, default_value_t = LogFormat::Auto)]
+    format: LogFormat,
+
+    /// TOML file of named-capture regexes, used by `--format pattern`
+    #[arg(long, required_if_eq("format", "pattern"))]
+    patterns: Option<PathBuf>,
+
+    /// Republish lines the parser rejects to this NATS subject
+    #[arg(long)]
+    dead_letter_subject: Option<String>,
 
     #[arg(long, default_value_t = 8080)]
     port: u16,
 
+    /// Bearer token enabling `POST /ingest` on the metrics port
+    #[arg(long, env = "LOG_ANALYZER_INGEST_TOKEN", hide_env_values = true)]
+    ingest_token: Option<String>,
+
+    /// Added as a `replica` label to every metric. Defaults to `$HOSTNAME`,
+    /// the pod name, when `--queue-group` is set and is left off otherwise.
+    #[arg(long)]
+    replica: Option<String>,
+
     #[arg(long, default_value_t = 0)]
     shutdown_after: u64,
 
@@ -41,8 +81,15 @@ struct Args {
     log_file: String,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Analyze log files or stdin and print a report instead of serving metrics
+    Analyze(analyze::AnalyzeArgs),
+}
+
 const INGEST_BUFFER_SIZE: usize = 50;
 const AGGREGATOR_BUFFER_SIZE: usize = 5;
+const DEAD_LETTER_BUFFER_SIZE: usize = 1_000;
 
 #[tokio::main]
 async fn main() -> Result<(), JoinError> {
@@ -50,6 +97,43 @@ async fn main() -> Result<(), JoinError> {
     let guard = ProfilerGuard::new(100).unwrap();
 
     let args = Args::parse();
+    if let Some(dead_letters) = &args.dead_letter_subject
+        && let Some(subject) = args
+            .ingest
+            .subject
+            .iter()
+            .find(|subject| ingest::subject_matches(subject, dead_letters))
+    {
+        // Rejected lines would be read back, rejected and republished forever.
+        Args::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "--dead-letter-subject {dead_letters} is subscribed to by --subject {subject}"
+                ),
+            )
+            .exit();
+    }
+    let limits = match &args.command {
+        Some(Command::Analyze(analyze_args)) => &analyze_args.limits,
+        None => &args.limits,
+    };
+    if let Err(e) = timeseries::check_nested(&limits.resolutions) {
+        Args::command()
+            .error(ErrorKind::ValueValidation, format!("--resolutions: {e}"))
+            .exit();
+    }
+    if let Some(Command::Analyze(analyze_args)) = args.command {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .with_env_filter(EnvFilter::from_default_env())
+            .init();
+        if let Err(e) = analyze::run(analyze_args).await {
+            eprintln!("log-analyzer: {e}");
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
     {
         #[allow(clippy::expect_used)]
         let file = File::create(&args.log_file).expect("Could not open log file");
@@ -60,15 +144,46 @@ async fn main() -> Result<(), JoinError> {
             .init();
     }
     info!("Starting log-analyzer");
-    let analytics = Arc::new(Analytics::default());
-    let metrics_handle = metrics_server::start(analytics.clone(), args.port);
-
+    let sources = Arc::new(Sources::new(args.limits));
     let (ingest_tx, ingest_rx) = mpsc::channel(INGEST_BUFFER_SIZE);
-    let (aggregator_tx, aggregator_rx) = mpsc::channel::<Vec<Metric>>(AGGREGATOR_BUFFER_SIZE);
+    let http_ingest = args.ingest_token.map(|token| HttpIngest {
+        token,
+        subjects: args.ingest.subject.clone(),
+        tx: ingest_tx.clone(),
+    });
+    let replica = args.replica.or_else(|| {
+        args.ingest
+            .queue_group
+            .as_ref()
+            .and_then(|_| std::env::var("HOSTNAME").ok())
+    });
+    let metrics_handle = metrics_server::start(sources.clone(), args.port, replica, http_ingest);
+
+    let (aggregator_tx, aggregator_rx) = mpsc::channel::<Batch>(AGGREGATOR_BUFFER_SIZE);
 
-    let nats_handle = spawn_nats_ingest(args.nats_url, args.subject, ingest_tx);
-    let worker_handle = spawn_workers(ingest_rx, aggregator_tx);
-    let aggregator_handle = spawn_aggregator(aggregator_rx, &analytics);
+    let (dead_letter_tx, dead_letters) = match args.dead_letter_subject {
+        Some(subject) => {
+            let (tx, rx) = mpsc::channel(DEAD_LETTER_BUFFER_SIZE);
+            (Some(tx), Some(DeadLetters { subject, rx }))
+        }
+        None => (None, None),
+    };
+
+    let parser = match args.format.parser(args.patterns.as_deref()) {
+        Ok(parser) => parser,
+        Err(e) => Args::command()
+            .error(ErrorKind::InvalidValue, format!("--patterns: {e}"))
+            .exit(),
+    };
+    let ingest_handle = spawn_ingest(args.ingest, ingest_tx, dead_letters);
+    let worker_handle = spawn_workers(
+        ingest_rx,
+        aggregator_tx,
+        parser,
+        args.source_tokens,
+        dead_letter_tx,
+    );
+    let aggregator_handle = spawn_aggregator(aggregator_rx, &sources);
 
     #[cfg(feature = "pprof")]
     {
@@ -82,7 +197,7 @@ async fn main() -> Result<(), JoinError> {
         if args.shutdown_after > 0 {
             tokio::select! {
                 _ = async {
-                    let _ = try_join!(nats_handle, worker_handle, aggregator_handle, metrics_handle);
+                    let _ = try_join!(ingest_handle, worker_handle, aggregator_handle, metrics_handle);
                 } => {
                     info!("All tasks completed before timeout.");
                 },
@@ -92,7 +207,7 @@ async fn main() -> Result<(), JoinError> {
             }
         } else {
             try_join!(
-                nats_handle,
+                ingest_handle,
                 worker_handle,
                 aggregator_handle,
                 metrics_handle
@@ -115,7 +230,7 @@ async fn main() -> Result<(), JoinError> {
 
     #[cfg(not(feature = "pprof"))]
     try_join!(
-        nats_handle,
+        ingest_handle,
         worker_handle,
         aggregator_handle,
         metrics_handle
@@ -124,37 +239,80 @@ async fn main() -> Result<(), JoinError> {
     Ok(())
 }
 
-fn spawn_nats_ingest(nats_url: String, subject: String, tx: Sender<String>) -> JoinHandle<()> {
-    tokio::spawn(async move {
-        if let Err(e) = consume_nats(nats_url, subject, tx).await {
-            tracing::error!("NATS ingest error: {e}");
-        }
-    })
+fn spawn_ingest(
+    args: IngestArgs,
+    tx: Sender<Chunk>,
+    dead_letters: Option<DeadLetters>,
+) -> JoinHandle<()> {
+    tokio::spawn(ingest::run(args, tx, dead_letters))
 }
 
-fn spawn_workers(rx: Receiver<String>, tx: Sender<Vec<Metric>>) -> JoinHandle<()> {
+fn spawn_workers(
+    rx: Receiver<Chunk>,
+    tx: Sender<Batch>,
+    parser: Box<dyn LogParser>,
+    source_tokens: Vec<usize>,
+    dead_letters: Option<Sender<DeadLetter>>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        worker_loop(tx, rx).await;
+        worker_loop(tx, rx, parser, source_tokens, dead_letters).await;
     })
 }
 
-fn spawn_aggregator(mut rx: Receiver<Vec<Metric>>, analytics: &Arc<Analytics>) -> JoinHandle<()> {
-    let analytics_clone = analytics.clone();
+fn spawn_aggregator(mut rx: Receiver<Batch>, sources: &Arc<Sources>) -> JoinHandle<()> {
+    let sources = sources.clone();
     tokio::spawn(async move {
-        while let Some(batch) = rx.recv().await {
-            for metric in batch {
-                match metric {
-                    Metric::Event(code) => analytics_clone.record_event(code),
-                    Metric::Path(path) => analytics_clone.record_path(&path),
-                    Metric::Host(host) => analytics_clone.record_host(&host),
-                    Metric::Hit(moment) => analytics_clone.record_hour_hit(moment.into()),
-                    Metric::HostBytes {
-                        host,
-                        timestamp,
-                        bytes,
-                    } => analytics_clone.record_host_hour_bytes(&host, timestamp.into(), bytes),
+        while let Some(Batch { metrics, acks }) = rx.recv().await {
+            for (source, metrics) in metrics {
+                let analytics = sources.get(&source);
+                for metric in metrics {
+                    match metric {
+                        Metric::Parsed(format) => analytics.record_parsed(format),
+                        Metric::ParseError {
+                            format,
+                            reason,
+                            line,
+                        } => analytics.record_parse_error(format, reason, &line),
+                        Metric::Redelivered(subject) => analytics.record_redelivery(&subject),
+                        Metric::SyslogSeverity(severity) => {
+                            analytics.record_syslog_severity(severity)
+                        }
+                        Metric::SyslogApp(app_name) => analytics.record_syslog_app(&app_name),
+                        Metric::Event(code) => analytics.record_event(code),
+                        Metric::Path(path) => analytics.record_path(&path),
+                        Metric::ResponseSize {
+                            path,
+                            status,
+                            bytes,
+                        } => analytics.record_response_size(&path, status, bytes),
+                        Metric::Host(host) => analytics.record_host(&host),
+                        Metric::Referrer(referrer) => analytics.record_referrer(&referrer),
+                        Metric::UserAgent(user_agent) => analytics.record_user_agent(&user_agent),
+                        Metric::Service(service) => analytics.record_service(&service),
+                        Metric::Level(level) => analytics.record_level(&level),
+                        Metric::Message(message) => analytics.record_message(&message),
+                        Metric::Extra { field, value } => analytics.record_extra(&field, &value),
+                        Metric::Hit {
+                            host,
+                            timestamp,
+                            status,
+                        } => analytics.record_hit(&host, timestamp, status),
+                        Metric::Visit {
+                            host,
+                            path,
+                            timestamp,
+                        } => analytics.record_visit(&host, &path, timestamp),
+                        Metric::HostBytes {
+                            host,
+                            timestamp,
+                            bytes,
+                        } => analytics.record_host_hour_bytes(&host, timestamp, bytes),
+                    }
                 }
             }
+            for ack in acks {
+                ack.ack();
+            }
         }
     })
 }
@@ -1,44 +1,92 @@
 use std::{
+    io::{self, Read},
     net::{IpAddr, Ipv4Addr, SocketAddr},
     sync::Arc,
 };
 
 use axum::{
     Router,
-    body::Body,
-    extract::State,
-    http::{HeaderValue, Response, header},
-    response::IntoResponse,
-    routing::get,
+    body::{Body, Bytes},
+    extract::{DefaultBodyLimit, Query, State},
+    http::{HeaderMap, HeaderValue, Response, StatusCode, header},
+    response::{IntoResponse, Json},
+    routing::{get, post},
+};
+use flate2::read::MultiGzDecoder;
+use prometheus::{Registry, TextEncoder};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    net::TcpListener,
+    signal,
+    sync::mpsc::{Sender, error::TrySendError},
+    task::JoinHandle,
+};
+
+use crate::{
+    analytics::{BadLine, Sources},
+    ingest::{self, Chunk},
 };
-use prometheus::TextEncoder;
-use tokio::{net::TcpListener, signal, task::JoinHandle};
 
-use crate::{analytics::Analytics, prometheus::PromMetrics};
+/// Largest body `/ingest` accepts, both as sent and once decompressed.
+const MAX_INGEST_BYTES: usize = 64 * 1024 * 1024;
+const DEFAULT_INGEST_SUBJECT: &str = "http";
+
+/// Lets shippers push lines with `POST /ingest`, see `--ingest-token`.
+#[derive(Clone)]
+pub struct HttpIngest {
+    pub token: String,
+    /// `--subject` patterns a pushed `?subject=` has to match, each subject
+    /// becomes a source that is kept for good.
+    pub subjects: Vec<String>,
+    pub tx: Sender<Chunk>,
+}
 
 #[derive(Clone)]
-struct Metrics(Arc<Analytics>, Arc<PromMetrics>);
+struct Metrics(Arc<Sources>, Registry, Option<HttpIngest>);
+
+#[derive(Deserialize)]
+struct IngestQuery {
+    subject: Option<String>,
+}
+
+#[derive(Serialize)]
+struct SourcedBadLine {
+    source: String,
+    #[serde(flatten)]
+    bad_line: BadLine,
+}
 
-pub fn start(analytics: Arc<Analytics>, port: u16) -> JoinHandle<()> {
+pub fn start(
+    sources: Arc<Sources>,
+    port: u16,
+    replica: Option<String>,
+    ingest: Option<HttpIngest>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let pro_metrics = Arc::new(PromMetrics::new());
+        let registry = crate::prometheus::registry(sources.clone(), replica);
         let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
         let listener = TcpListener::bind(addr).await.unwrap();
-        axum::serve(listener, router(Metrics(analytics, pro_metrics)))
+        axum::serve(listener, router(Metrics(sources, registry, ingest)))
             .with_graceful_shutdown(shutdown_signal())
             .await
             .unwrap();
     })
 }
 fn router(metrics: Metrics) -> Router {
-    Router::new()
+    let mut router = Router::new()
         .route("/up", get(up))
         .route("/metrics", get(handler))
-        .with_state(metrics)
+        .route("/parse-errors", get(parse_errors));
+    if metrics.2.is_some() {
+        router = router.route(
+            "/ingest",
+            post(ingest).layer(DefaultBodyLimit::max(MAX_INGEST_BYTES)),
+        );
+    }
+    router.with_state(metrics)
 }
-async fn handler(State(Metrics(analytics, pro_metrics)): State<Metrics>) -> Response<Body> {
-    analytics.export_to_prometheus(&pro_metrics);
-    let metric_families = pro_metrics.registry.gather();
+async fn handler(State(Metrics(_, registry, _)): State<Metrics>) -> Response<Body> {
+    let metric_families = registry.gather();
     let mut buffer = String::new();
     let encoder = TextEncoder::new();
     encoder.encode_utf8(&metric_families, &mut buffer).unwrap();
@@ -51,6 +99,104 @@ async fn handler(State(Metrics(analytics, pro_metrics)): State<Metrics>) -> Resp
     )
         .into_response()
 }
+/// Recently rejected log lines with their source, the format tried and the
+/// failing field.
+async fn parse_errors(State(Metrics(sources, _, _)): State<Metrics>) -> Response<Body> {
+    let bad_lines: Vec<_> = sources
+        .all()
+        .into_iter()
+        .flat_map(|(source, analytics)| {
+            analytics
+                .recent_bad_lines()
+                .into_iter()
+                .map(move |bad_line| SourcedBadLine {
+                    source: source.clone(),
+                    bad_line,
+                })
+        })
+        .collect();
+    Json(bad_lines).into_response()
+}
+/// Newline separated log lines, gzipped when sent with
+/// `Content-Encoding: gzip`. Answers 429 while the workers are behind so the
+/// shipper can retry.
+async fn ingest(
+    State(Metrics(_, _, ingest)): State<Metrics>,
+    Query(IngestQuery { subject }): Query<IngestQuery>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response<Body> {
+    let Some(HttpIngest {
+        token,
+        subjects,
+        tx,
+    }) = ingest
+    else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| constant_time_eq(presented.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let subject = match subject {
+        Some(subject)
+            if subjects
+                .iter()
+                .any(|pattern| ingest::subject_matches(pattern, &subject)) =>
+        {
+            subject
+        }
+        Some(_) => return StatusCode::BAD_REQUEST.into_response(),
+        None => DEFAULT_INGEST_SUBJECT.to_owned(),
+    };
+    let gzipped = headers
+        .get(header::CONTENT_ENCODING)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"gzip"));
+    let payload = if gzipped {
+        // Inflating up to the limit takes a while, keep it off the runtime.
+        match tokio::task::spawn_blocking(move || gunzip(&body)).await {
+            Ok(Ok(payload)) if payload.len() > MAX_INGEST_BYTES => {
+                return StatusCode::PAYLOAD_TOO_LARGE.into_response();
+            }
+            Ok(Ok(payload)) => payload,
+            Ok(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    } else {
+        body.to_vec()
+    };
+    let chunk = Chunk {
+        subject,
+        payload: String::from_utf8_lossy(&payload).into_owned(),
+        redelivered: false,
+        syslog: None,
+        ack: None,
+    };
+    match tx.try_send(chunk) {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(TrySendError::Full(_)) => (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, HeaderValue::from_static("1"))],
+        )
+            .into_response(),
+        Err(TrySendError::Closed(_)) => StatusCode::SERVICE_UNAVAILABLE.into_response(),
+    }
+}
+/// Decompresses `body`, stopping one byte past [`MAX_INGEST_BYTES`].
+fn gunzip(body: &[u8]) -> io::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    MultiGzDecoder::new(body)
+        .take(MAX_INGEST_BYTES as u64 + 1)
+        .read_to_end(&mut payload)?;
+    Ok(payload)
+}
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
 async fn up() -> Response<Body> {
     ().into_response()
 }
@@ -78,3 +224,86 @@ async fn shutdown_signal() {
         _ = terminate => {},
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use axum::http::Request;
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+    use tokio::sync::mpsc::{self, Receiver};
+    use tower::ServiceExt;
+
+    fn app(capacity: usize) -> (Router, Receiver<Chunk>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let sources = Arc::new(Sources::default());
+        let registry = crate::prometheus::registry(sources.clone(), None);
+        let ingest = HttpIngest {
+            token: "secret".into(),
+            subjects: vec!["edge".into()],
+            tx,
+        };
+        (router(Metrics(sources, registry, Some(ingest))), rx)
+    }
+
+    fn push(token: &str, body: impl Into<Body>) -> Request<Body> {
+        Request::post("/ingest?subject=edge")
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .body(body.into())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn ingest_requires_the_token() {
+        let (app, _rx) = app(1);
+        let response = app.oneshot(push("wrong", "line\n")).await.unwrap();
+        assert_that!(response.status()).is_equal_to(StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn ingest_only_takes_configured_subjects() {
+        let (app, _rx) = app(1);
+        let request = Request::post("/ingest?subject=anything")
+            .header(header::AUTHORIZATION, "Bearer secret")
+            .body(Body::from("line\n"))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_that!(response.status()).is_equal_to(StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn ingest_forwards_gzipped_lines() {
+        let (app, mut rx) = app(1);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"one\ntwo\n").unwrap();
+        let mut request = push("secret", encoder.finish().unwrap());
+        request
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_that!(response.status()).is_equal_to(StatusCode::ACCEPTED);
+        let chunk = rx.recv().await.unwrap();
+        assert_that!(chunk.subject).is_equal_to("edge".to_owned());
+        assert_that!(chunk.payload).is_equal_to("one\ntwo\n".to_owned());
+    }
+
+    #[tokio::test]
+    async fn ingest_accepts_bodies_past_the_default_limit() {
+        let (app, mut rx) = app(1);
+        let body = "line\n".repeat(1024 * 1024);
+        let response = app.oneshot(push("secret", body.clone())).await.unwrap();
+        assert_that!(response.status()).is_equal_to(StatusCode::ACCEPTED);
+        assert_that!(rx.recv().await.unwrap().payload).is_equal_to(body);
+    }
+
+    #[tokio::test]
+    async fn ingest_pushes_back_when_the_channel_is_full() {
+        let (app, _rx) = app(1);
+        let first = app.clone().oneshot(push("secret", "one\n")).await.unwrap();
+        assert_that!(first.status()).is_equal_to(StatusCode::ACCEPTED);
+        let second = app.oneshot(push("secret", "two\n")).await.unwrap();
+        assert_that!(second.status()).is_equal_to(StatusCode::TOO_MANY_REQUESTS);
+    }
+}
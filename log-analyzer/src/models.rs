@@ -1,10 +1,84 @@
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
+use derive_more::Display;
+use serde::Deserialize;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LogEntry {
     pub host: String,
     pub timestamp: DateTime<Utc>,
+    pub method: String,
     pub path: String,
+    pub protocol: Option<String>,
     pub status: u16,
     pub bytes: u64,
+    pub referrer: Option<String>,
+    pub user_agent: Option<String>,
+    /// Additional named captures from user defined patterns.
+    pub extra: BTreeMap<String, String>,
+}
+
+/// A structured application log line, e.g. noise-maker's `--format json`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct StructuredLog {
+    #[serde(rename = "ts")]
+    pub timestamp: DateTime<Utc>,
+    pub service: String,
+    pub level: String,
+    #[serde(rename = "msg")]
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LogRecord {
+    Access(LogEntry),
+    Structured(StructuredLog),
+}
+
+/// Syslog severity, RFC 5424 section 6.2.1.
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Severity {
+    #[display("emergency")]
+    Emergency,
+    #[display("alert")]
+    Alert,
+    #[display("critical")]
+    Critical,
+    #[display("error")]
+    Error,
+    #[display("warning")]
+    Warning,
+    #[display("notice")]
+    Notice,
+    #[display("info")]
+    Info,
+    #[display("debug")]
+    Debug,
+}
+
+impl Severity {
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0 => Self::Emergency,
+            1 => Self::Alert,
+            2 => Self::Critical,
+            3 => Self::Error,
+            4 => Self::Warning,
+            5 => Self::Notice,
+            6 => Self::Info,
+            7 => Self::Debug,
+            _ => return None,
+        })
+    }
+}
+
+/// The syslog header wrapped around a log line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SyslogEnvelope {
+    pub facility: u8,
+    pub severity: Severity,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
 }
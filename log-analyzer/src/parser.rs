@@ -0,0 +1,476 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{
+    models::{LogEntry, LogRecord, StructuredLog},
+    pattern::PatternParser,
+};
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use clap::ValueEnum;
+use derive_more::{Display, Error};
+use serde::Serialize;
+
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Common Log Format
+    #[display("apache")]
+    Apache,
+    /// Combined Log Format, CLF with trailing referrer and user-agent
+    #[display("combined")]
+    Combined,
+    /// JSON objects with `ts`, `service`, `level` and `msg` fields
+    #[display("json")]
+    Json,
+    /// Sniff the format of every line
+    #[display("auto")]
+    Auto,
+    /// User defined regexes loaded from `--patterns`
+    #[display("pattern")]
+    Pattern,
+}
+
+impl LogFormat {
+    pub fn parser(
+        self,
+        patterns: Option<&Path>,
+    ) -> Result<Box<dyn LogParser>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            Self::Apache => Box::new(ApacheParser),
+            Self::Combined => Box::new(CombinedParser),
+            Self::Json => Box::new(JsonParser),
+            Self::Auto => Box::new(AutoParser),
+            Self::Pattern => Box::new(PatternParser::load(
+                patterns.ok_or("--format pattern requires --patterns")?,
+            )?),
+        })
+    }
+}
+
+/// Why a line was rejected, named after the field that failed to parse.
+#[derive(Debug, Display, Error, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ParseError {
+    /// The line does not have the expected shape at all
+    #[display("structure")]
+    Structure,
+    #[display("request")]
+    Request,
+    #[display("timestamp")]
+    Timestamp,
+    #[display("status")]
+    Status,
+    #[display("bytes")]
+    Bytes,
+}
+
+pub trait LogParser: Send + Sync {
+    /// Parses `line`, along with the concrete format it was parsed as. Only
+    /// [`AutoParser`] looks at the line, the others always report their own
+    /// format.
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>);
+}
+
+pub struct ApacheParser;
+
+impl LogParser for ApacheParser {
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>) {
+        let record = parse_log_line(line, LogFormat::Apache).map(LogRecord::Access);
+        (LogFormat::Apache, record)
+    }
+}
+
+pub struct CombinedParser;
+
+impl LogParser for CombinedParser {
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>) {
+        let record = parse_log_line(line, LogFormat::Combined).map(LogRecord::Access);
+        (LogFormat::Combined, record)
+    }
+}
+
+pub struct JsonParser;
+
+impl LogParser for JsonParser {
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>) {
+        (
+            LogFormat::Json,
+            parse_json_line(line).map(LogRecord::Structured),
+        )
+    }
+}
+
+/// Picks a parser per line, for subjects carrying a mix of formats.
+pub struct AutoParser;
+
+impl LogParser for AutoParser {
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>) {
+        let trimmed = line.trim();
+        if trimmed.starts_with('{') {
+            JsonParser.parse(line)
+        } else if trimmed.ends_with('"') {
+            CombinedParser.parse(line)
+        } else {
+            ApacheParser.parse(line)
+        }
+    }
+}
+
+fn parse_json_line(line: &str) -> Result<StructuredLog, ParseError> {
+    serde_json::from_str(line).map_err(|_| {
+        // Only pay for working out which field failed on the error path.
+        let ts_is_invalid = serde_json::from_str::<serde_json::Value>(line)
+            .ok()
+            .and_then(|v| v.get("ts").cloned())
+            .is_some_and(|ts| serde_json::from_value::<DateTime<Utc>>(ts).is_err());
+        if ts_is_invalid {
+            ParseError::Timestamp
+        } else {
+            ParseError::Structure
+        }
+    })
+}
+
+/// Parses a Common Log Format line. Combined Log Format trailers are picked
+/// up when present and required when `format` is [`LogFormat::Combined`].
+fn parse_log_line(line: &str, format: LogFormat) -> Result<LogEntry, ParseError> {
+    use ParseError::Structure;
+    let mut fields = Fields(line);
+    let host = fields.bare().ok_or(Structure)?;
+    fields.bare().ok_or(Structure)?; // skip ident
+    fields.bare().ok_or(Structure)?; // skip authuser
+    let ts = fields.bracketed().ok_or(Structure)?;
+    let request = fields.quoted().ok_or(Structure)?;
+    let status: u16 = fields
+        .bare()
+        .ok_or(Structure)?
+        .parse()
+        .map_err(|_| ParseError::Status)?;
+    let bytes = match fields.bare().ok_or(Structure)? {
+        "-" => 0,
+        s => s.parse().map_err(|_| ParseError::Bytes)?,
+    };
+    // Combined Log Format appends "referer" "user-agent"; plain CLF stops here.
+    let (referrer, user_agent) = match (fields.quoted(), fields.quoted()) {
+        (Some(referrer), Some(user_agent)) => {
+            (absent_as_none(referrer), absent_as_none(user_agent))
+        }
+        (referrer, _) if format != LogFormat::Combined => (referrer.and_then(absent_as_none), None),
+        _ => return Err(Structure),
+    };
+    let (method, path, protocol) = split_request_line(&request).ok_or(ParseError::Request)?;
+    let dt = parse_apache_timestamp(ts).ok_or(ParseError::Timestamp)?;
+    Ok(LogEntry {
+        host: host.to_owned(),
+        timestamp: dt,
+        method: method.to_owned(),
+        path: path.to_owned(),
+        protocol: protocol.map(str::to_owned),
+        status,
+        bytes,
+        referrer,
+        user_agent,
+        extra: BTreeMap::new(),
+    })
+}
+
+/// Cursor over the space separated fields of a Common/Combined Log Format line.
+struct Fields<'a>(&'a str);
+
+impl<'a> Fields<'a> {
+    fn bare(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (field, rest) = rest.split_at(end);
+        self.0 = rest;
+        Some(field)
+    }
+
+    fn bracketed(&mut self) -> Option<&'a str> {
+        let rest = self.0.trim_start().strip_prefix('[')?;
+        let (field, rest) = rest.split_once(']')?;
+        self.0 = rest;
+        Some(field)
+    }
+
+    /// Reads a double quoted field, unescaping `\"` and `\\`.
+    fn quoted(&mut self) -> Option<String> {
+        let rest = self.0.trim_start().strip_prefix('"')?;
+        let mut field = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.0 = &rest[i + 1..];
+                    return Some(field);
+                }
+                '\\' => match chars.next()? {
+                    (_, e @ ('"' | '\\')) => field.push(e),
+                    (_, e) => {
+                        field.push('\\');
+                        field.push(e);
+                    }
+                },
+                c => field.push(c),
+            }
+        }
+        None
+    }
+}
+
+fn absent_as_none(field: String) -> Option<String> {
+    match field.as_str() {
+        "" | "-" => None,
+        _ => Some(field),
+    }
+}
+
+/// Splits `METHOD PATH [PROTOCOL]` into its parts. The protocol is optional
+/// (HTTP/0.9 style requests) and the path may itself contain spaces.
+fn split_request_line(request: &str) -> Option<(&str, &str, Option<&str>)> {
+    let (method, rest) = request.trim().split_once(' ')?;
+    let rest = rest.trim();
+    let (path, protocol) = match rest.rsplit_once(' ') {
+        Some((path, protocol)) if protocol.starts_with("HTTP/") => {
+            (path.trim_end(), Some(protocol))
+        }
+        _ => (rest, None),
+    };
+    if path.is_empty() {
+        return None;
+    }
+    Some((method, path, protocol))
+}
+
+fn parse_apache_timestamp(ts: &str) -> Option<DateTime<Utc>> {
+    let mut parts = ts.splitn(7, ['/', ':', ' ']);
+    let day: u32 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i32 = parts.next()?.parse().ok()?;
+    let hour: u32 = parts.next()?.parse().ok()?;
+    let min: u32 = parts.next()?.parse().ok()?;
+    let sec: u32 = parts.next()?.parse().ok()?;
+    let offset: i32 = parts.next().map(|s| s.parse::<i32>().ok())??;
+    let offset_hours = offset / 100;
+    let offset_min = offset - (offset_hours * 100);
+    Utc.with_ymd_and_hms(year, month, day, hour, min, sec)
+        .single()
+        .map(|t| t - TimeDelta::hours(offset_hours.into()) - TimeDelta::minutes(offset_min.into()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use asserting::{expectations::IsEqualTo, prelude::*};
+    use chrono::prelude::*;
+    use noise_maker::generator::{generate_apache_log, generate_json_log};
+    use rand::{SeedableRng, rngs::StdRng};
+
+    #[test]
+    fn parse_log_line_valid() {
+        let line =
+            r#"202.32.92.47 - - [01/Jun/1995:00:00:59 -0600] "GET /~scottp/publish.html" 200 271"#;
+        assert_that!(parse_log_line(line, LogFormat::Apache))
+            .is_ok()
+            .mapping(|o| o.unwrap())
+            .expecting(IsEqualTo {
+                expected: LogEntry {
+                    host: "202.32.92.47".into(),
+                    timestamp: FixedOffset::west_opt(6 * 3600)
+                        .unwrap()
+                        .with_ymd_and_hms(1995, 6, 1, 0, 0, 59)
+                        .unwrap()
+                        .with_timezone(&Utc),
+                    method: "GET".into(),
+                    path: "/~scottp/publish.html".into(),
+                    protocol: None,
+                    status: 200,
+                    bytes: 271,
+                    referrer: None,
+                    user_agent: None,
+                    extra: BTreeMap::new(),
+                },
+            });
+    }
+
+    #[test]
+    fn parse_log_line_with_protocol_version() {
+        let line = r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#;
+        let entry = parse_log_line(line, LogFormat::Apache).unwrap();
+        assert_that!(entry.path).is_equal_to("/api".to_string());
+        assert_that!(entry.status).is_equal_to(200);
+        assert_that!(entry.bytes).is_equal_to(512);
+    }
+
+    #[test]
+    fn parse_log_line_with_escaped_quotes() {
+        let line =
+            r#"10.0.0.1 - frank [10/Oct/2000:13:55:36 -0700] "GET /say?q=\"hi\" HTTP/1.0" 404 -"#;
+        let entry = parse_log_line(line, LogFormat::Apache).unwrap();
+        assert_that!(entry.path).is_equal_to(r#"/say?q="hi""#.to_string());
+        assert_that!(entry.status).is_equal_to(404);
+        assert_that!(entry.bytes).is_equal_to(0);
+    }
+
+    #[test]
+    fn parse_log_line_combined() {
+        let line = r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "POST /a b HTTP/2.0" 201 7 "https://example.com/" "Mozilla/5.0 (X11) Firefox/128.0""#;
+        let entry = parse_log_line(line, LogFormat::Apache).unwrap();
+        assert_that!(entry.method).is_equal_to("POST".to_string());
+        assert_that!(entry.path).is_equal_to("/a b".to_string());
+        assert_that!(entry.protocol).is_equal_to(Some("HTTP/2.0".to_string()));
+        assert_that!(entry.status).is_equal_to(201);
+        assert_that!(entry.referrer).is_equal_to(Some("https://example.com/".to_string()));
+        assert_that!(entry.user_agent)
+            .is_equal_to(Some("Mozilla/5.0 (X11) Firefox/128.0".to_string()));
+    }
+
+    #[test]
+    fn parse_log_line_combined_without_referrer() {
+        let line =
+            r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 7 "-" "curl/8.0""#;
+        let entry = parse_log_line(line, LogFormat::Apache).unwrap();
+        assert_that!(entry.referrer).is_none();
+        assert_that!(entry.user_agent).is_equal_to(Some("curl/8.0".to_string()));
+    }
+
+    #[test]
+    fn parse_log_line_reports_failing_field() {
+        for (line, reason) in [
+            ("", ParseError::Structure),
+            ("garbage", ParseError::Structure),
+            (
+                r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /api HTTP/1.1 200 512"#,
+                ParseError::Structure,
+            ),
+            (
+                r#"10.0.0.1 - - 10/Oct/2000:13:55:36 -0700 "GET /api HTTP/1.1" 200 512"#,
+                ParseError::Structure,
+            ),
+            (
+                r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /api HTTP/1.1" OK 512"#,
+                ParseError::Status,
+            ),
+            (
+                r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET /api HTTP/1.1" 200 lots"#,
+                ParseError::Bytes,
+            ),
+            (
+                r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET" 200 512"#,
+                ParseError::Request,
+            ),
+            (
+                r#"10.0.0.1 - - [10/Foo/2000:13:55:36 -0700] "GET /api HTTP/1.1" 200 512"#,
+                ParseError::Timestamp,
+            ),
+        ] {
+            assert_that!(parse_log_line(line, LogFormat::Apache)).is_equal_to(Err(reason));
+        }
+    }
+
+    #[test]
+    fn parse_log_line_accepts_noise_maker_output() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..1_000 {
+            let line = generate_apache_log(&mut rng);
+            let entry = parse_log_line(&line, LogFormat::Apache);
+            assert!(entry.is_ok(), "failed to parse {line}");
+        }
+    }
+
+    #[test]
+    fn parse_json_line_valid() {
+        let line = r#"{"ts":"2025-07-25T23:59:59.123+02:00","service":"api","level":"WARN","msg":"Cache miss"}"#;
+        assert_that!(parse_json_line(line))
+            .is_ok()
+            .mapping(|o| o.unwrap())
+            .expecting(IsEqualTo {
+                expected: StructuredLog {
+                    timestamp: Utc
+                        .with_ymd_and_hms(2025, 7, 25, 21, 59, 59)
+                        .unwrap()
+                        .with_nanosecond(123_000_000)
+                        .unwrap(),
+                    service: "api".into(),
+                    level: "WARN".into(),
+                    message: "Cache miss".into(),
+                },
+            });
+    }
+
+    #[test]
+    fn parse_json_line_rejects_missing_fields() {
+        assert_that!(parse_json_line(
+            r#"{"ts":"2025-07-25T23:59:59Z","service":"api"}"#
+        ))
+        .is_equal_to(Err(ParseError::Structure));
+        assert_that!(parse_json_line(
+            r#"{"ts":"yesterday","service":"api","level":"INFO","msg":"x"}"#
+        ))
+        .is_equal_to(Err(ParseError::Timestamp));
+        assert_that!(parse_json_line("{not json")).is_equal_to(Err(ParseError::Structure));
+    }
+
+    #[test]
+    fn parse_line_accepts_noise_maker_json_output() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..1_000 {
+            let line = generate_json_log(&mut rng);
+            let (_, record) = AutoParser.parse(&line);
+            assert!(
+                matches!(record, Ok(LogRecord::Structured(_))),
+                "failed to parse {line}"
+            );
+        }
+    }
+
+    #[test]
+    fn combined_parser_requires_trailer() {
+        let clf = r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 7"#;
+        let combined =
+            r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 7 "-" "curl/8.0""#;
+        assert_that!(CombinedParser.parse(clf).1).is_equal_to(Err(ParseError::Structure));
+        assert_that!(CombinedParser.parse(combined).1).is_ok();
+        assert_that!(ApacheParser.parse(clf).1).is_ok();
+        assert_that!(ApacheParser.parse(combined).1).is_ok();
+    }
+
+    #[test]
+    fn auto_parser_detects_format_per_line() {
+        let clf = r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 7"#;
+        let combined =
+            r#"10.0.0.1 - - [10/Oct/2000:13:55:36 -0700] "GET / HTTP/1.1" 200 7 "-" "curl/8.0""#;
+        let json = r#"{"ts":"2025-07-25T23:59:59Z","service":"api","level":"INFO","msg":"x"}"#;
+        assert_that!(AutoParser.parse(clf).0).is_equal_to(LogFormat::Apache);
+        assert!(matches!(
+            AutoParser.parse(json),
+            (LogFormat::Json, Ok(LogRecord::Structured(_)))
+        ));
+        assert!(matches!(
+            AutoParser.parse(combined),
+            (
+                LogFormat::Combined,
+                Ok(LogRecord::Access(LogEntry {
+                    user_agent: Some(_),
+                    ..
+                }))
+            )
+        ));
+    }
+}
@@ -0,0 +1,234 @@
+use std::{collections::BTreeMap, fs, path::Path};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::{Captures, Regex};
+use serde::Deserialize;
+
+use crate::{
+    models::{LogEntry, LogRecord},
+    parser::{LogFormat, LogParser, ParseError},
+};
+
+/// Captures mapped onto dedicated [`LogEntry`] fields. Any other named
+/// capture ends up in [`LogEntry::extra`].
+const REQUIRED_FIELDS: [&str; 4] = ["host", "timestamp", "path", "status"];
+const OPTIONAL_FIELDS: [&str; 5] = ["bytes", "method", "protocol", "referrer", "user_agent"];
+
+/// On-disk pattern file, e.g.
+///
+/// ```toml
+/// [[pattern]]
+/// name = "billing"
+/// regex = '^(?P<timestamp>\S+) (?P<host>\S+) (?P<path>\S+) (?P<status>\d{3})$'
+/// timestamp_format = "%Y-%m-%dT%H:%M:%S%z"
+/// ```
+#[derive(Debug, Deserialize)]
+struct PatternConfig {
+    #[serde(rename = "pattern", default)]
+    patterns: Vec<PatternSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PatternSpec {
+    name: String,
+    regex: String,
+    #[serde(default = "default_timestamp_format")]
+    timestamp_format: String,
+}
+
+fn default_timestamp_format() -> String {
+    "%d/%b/%Y:%H:%M:%S %z".into()
+}
+
+#[derive(Debug)]
+struct Pattern {
+    name: String,
+    regex: Regex,
+    timestamp_format: String,
+    extra: Vec<String>,
+}
+
+/// Parses bespoke access logs with user supplied named-capture regexes. The
+/// patterns are tried in file order and the first match wins.
+#[derive(Debug)]
+pub struct PatternParser {
+    patterns: Vec<Pattern>,
+}
+
+impl PatternParser {
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(config: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let config: PatternConfig = toml::from_str(config)?;
+        if config.patterns.is_empty() {
+            return Err("pattern file defines no [[pattern]] entries".into());
+        }
+        let patterns = config
+            .patterns
+            .into_iter()
+            .map(Pattern::compile)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { patterns })
+    }
+}
+
+impl Pattern {
+    fn compile(spec: PatternSpec) -> Result<Self, Box<dyn std::error::Error>> {
+        let regex = Regex::new(&spec.regex)
+            .map_err(|e| format!("pattern {:?}: invalid regex: {e}", spec.name))?;
+        let names: Vec<_> = regex.capture_names().flatten().collect();
+        if let Some(missing) = REQUIRED_FIELDS.iter().find(|f| !names.contains(f)) {
+            return Err(
+                format!("pattern {:?}: missing capture group {missing:?}", spec.name).into(),
+            );
+        }
+        let extra = names
+            .into_iter()
+            .filter(|n| !REQUIRED_FIELDS.contains(n) && !OPTIONAL_FIELDS.contains(n))
+            .map(str::to_owned)
+            .collect();
+        Ok(Self {
+            name: spec.name,
+            regex,
+            timestamp_format: spec.timestamp_format,
+            extra,
+        })
+    }
+
+    /// `None` if the regex does not match `line` at all.
+    fn parse(&self, line: &str) -> Option<Result<LogEntry, ParseError>> {
+        let caps = self.regex.captures(line)?;
+        Some(self.to_entry(&caps))
+    }
+
+    fn to_entry(&self, caps: &Captures) -> Result<LogEntry, ParseError> {
+        let field = |name: &str| {
+            caps.name(name)
+                .map(|m| m.as_str())
+                .filter(|s| !s.is_empty())
+        };
+        let required = |name: &str| field(name).ok_or(ParseError::Structure);
+        let owned = |name: &str| field(name).filter(|s| *s != "-").map(str::to_owned);
+        let bytes = match field("bytes") {
+            None | Some("-") => 0,
+            Some(s) => s.parse().map_err(|_| ParseError::Bytes)?,
+        };
+        Ok(LogEntry {
+            host: required("host")?.to_owned(),
+            timestamp: parse_timestamp(required("timestamp")?, &self.timestamp_format)
+                .ok_or(ParseError::Timestamp)?,
+            method: owned("method").unwrap_or_default(),
+            path: required("path")?.to_owned(),
+            protocol: owned("protocol"),
+            status: required("status")?
+                .parse()
+                .map_err(|_| ParseError::Status)?,
+            bytes,
+            referrer: owned("referrer"),
+            user_agent: owned("user_agent"),
+            extra: self
+                .extra
+                .iter()
+                .filter_map(|name| Some((name.clone(), field(name)?.to_owned())))
+                .collect::<BTreeMap<_, _>>(),
+        })
+    }
+}
+
+impl LogParser for PatternParser {
+    fn parse(&self, line: &str) -> (LogFormat, Result<LogRecord, ParseError>) {
+        let record = self
+            .patterns
+            .iter()
+            .find_map(|p| {
+                let entry = p.parse(line)?;
+                tracing::trace!("line matched pattern {}", p.name);
+                Some(entry.map(LogRecord::Access))
+            })
+            .unwrap_or(Err(ParseError::Structure));
+        (LogFormat::Pattern, record)
+    }
+}
+
+/// Timestamps without an offset in `format` are taken to be UTC.
+fn parse_timestamp(ts: &str, format: &str) -> Option<DateTime<Utc>> {
+    match format {
+        "rfc3339" => DateTime::parse_from_rfc3339(ts).ok().map(|t| t.to_utc()),
+        _ => DateTime::parse_from_str(ts, format)
+            .map(|t| t.to_utc())
+            .or_else(|_| NaiveDateTime::parse_from_str(ts, format).map(|t| t.and_utc()))
+            .ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use chrono::TimeZone;
+
+    const CONFIG: &str = r#"
+[[pattern]]
+name = "billing"
+regex = '^(?P<timestamp>\S+ \S+) \[(?P<tenant>\w+)\] (?P<host>\S+) (?P<method>[A-Z]+) (?P<path>\S+) -> (?P<status>\d{3})(?: (?P<bytes>\d+|-))?$'
+timestamp_format = "%Y-%m-%d %H:%M:%S"
+
+[[pattern]]
+name = "fallback"
+regex = '^(?P<host>\S+) (?P<timestamp>\S+) (?P<path>\S+) (?P<status>\d{3})$'
+timestamp_format = "rfc3339"
+"#;
+
+    #[test]
+    fn pattern_maps_captures_onto_log_entry() {
+        let parser = PatternParser::from_toml(CONFIG).unwrap();
+        let line = "2025-07-25 23:59:59 [acme] 10.0.0.1 POST /invoices -> 201 512";
+        let (_, Ok(LogRecord::Access(entry))) = parser.parse(line) else {
+            panic!("line did not match");
+        };
+        assert_that!(entry.host).is_equal_to("10.0.0.1".to_string());
+        assert_that!(entry.timestamp)
+            .is_equal_to(Utc.with_ymd_and_hms(2025, 7, 25, 23, 59, 59).unwrap());
+        assert_that!(entry.method).is_equal_to("POST".to_string());
+        assert_that!(entry.path).is_equal_to("/invoices".to_string());
+        assert_that!(entry.status).is_equal_to(201);
+        assert_that!(entry.bytes).is_equal_to(512);
+        assert_that!(entry.extra.get("tenant")).is_equal_to(Some(&"acme".to_string()));
+    }
+
+    #[test]
+    fn patterns_are_tried_in_order() {
+        let parser = PatternParser::from_toml(CONFIG).unwrap();
+        let line = "10.0.0.2 2025-07-25T23:59:59+02:00 /health 200";
+        let (_, Ok(LogRecord::Access(entry))) = parser.parse(line) else {
+            panic!("line did not match");
+        };
+        assert_that!(entry.timestamp)
+            .is_equal_to(Utc.with_ymd_and_hms(2025, 7, 25, 21, 59, 59).unwrap());
+        assert_that!(entry.bytes).is_equal_to(0);
+        assert_that!(parser.parse("nothing to see here").1).is_equal_to(Err(ParseError::Structure));
+        assert_that!(parser.parse("10.0.0.2 yesterday /health 200").1)
+            .is_equal_to(Err(ParseError::Timestamp));
+    }
+
+    #[test]
+    fn config_requires_core_captures() {
+        let config = r#"
+[[pattern]]
+name = "broken"
+regex = '^(?P<host>\S+) (?P<path>\S+)$'
+"#;
+        assert_that!(PatternParser::from_toml(config)).is_err();
+        assert_that!(PatternParser::from_toml("")).is_err();
+    }
+
+    #[test]
+    fn example_config_is_valid() {
+        assert_that!(PatternParser::from_toml(include_str!(
+            "../patterns.example.toml"
+        )))
+        .is_ok();
+    }
+}
@@ -1,48 +1,556 @@
-use prometheus::{IntCounterVec, IntGaugeVec, Registry, opts, register_int_counter_vec};
+use std::{collections::HashMap, sync::Arc};
 
+use parking_lot::Mutex;
+use prometheus::{
+    GaugeVec, IntCounterVec, IntGaugeVec, Registry,
+    core::{Collector, Desc},
+    opts,
+    proto::{self, LabelPair, MetricFamily, MetricType},
+};
+
+use crate::{analytics::Sources, histogram::Histogram};
+
+/// Registry exposing every source's analytics under `/metrics`.
+///
+/// Every replica only sees the share of logs its queue group hands it, so
+/// when `replica` is set each series carries it as a `replica` label and
+/// replicas can be summed in Prometheus rather than overwriting each other.
+pub fn registry(sources: Arc<Sources>, replica: Option<String>) -> Registry {
+    let labels = replica.map(|replica| HashMap::from([("replica".to_owned(), replica)]));
+    let registry = Registry::new_custom(None, labels).unwrap();
+    registry
+        .register(Box::new(AnalyticsCollector::new(sources)))
+        .unwrap();
+    registry
+}
+
+/// Reads every source's [`Analytics`] on every scrape. Counters are filled
+/// with the current totals into a fresh [`PromMetrics`], so repeated or
+/// concurrent scrapes never add to each other.
+///
+/// [`Analytics`]: crate::analytics::Analytics
+pub struct AnalyticsCollector {
+    sources: Arc<Sources>,
+    descs: Vec<Desc>,
+}
+
+impl AnalyticsCollector {
+    pub fn new(sources: Arc<Sources>) -> Self {
+        let descs = PromMetrics::new()
+            .collectors()
+            .iter()
+            .flat_map(|c| c.desc().into_iter().cloned())
+            .collect();
+        Self { sources, descs }
+    }
+}
+
+impl Collector for AnalyticsCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        self.descs.iter().collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let metrics = PromMetrics::new();
+        for (source, analytics) in self.sources.all() {
+            analytics.export_to_prometheus(&source, &metrics);
+        }
+        metrics
+            .collectors()
+            .iter()
+            .flat_map(|c| c.collect())
+            .collect()
+    }
+}
+
+/// A histogram family whose buckets are copied from a [`Histogram`]. The
+/// prometheus crate's own `HistogramVec` can only observe values one by
+/// one, and [`PromMetrics`] is filled from totals.
+pub struct HistogramFamily {
+    desc: Desc,
+    metrics: Mutex<Vec<proto::Metric>>,
+}
+
+impl HistogramFamily {
+    pub fn new(name: &str, help: &str, labels: &[&str]) -> Self {
+        let labels = labels.iter().map(|l| l.to_string()).collect();
+        Self {
+            desc: Desc::new(name.into(), help.into(), labels, HashMap::new()).unwrap(),
+            metrics: Mutex::default(),
+        }
+    }
+
+    pub fn set(&self, label_values: &[&str], histogram: &Histogram) {
+        let mut labels: Vec<_> = self
+            .desc
+            .variable_labels
+            .iter()
+            .zip(label_values)
+            .map(|(name, value)| {
+                let mut label = LabelPair::default();
+                label.set_name(name.clone());
+                label.set_value(value.to_string());
+                label
+            })
+            .collect();
+        labels.sort_by(|a, b| a.name().cmp(b.name()));
+
+        let mut proto_histogram = proto::Histogram::default();
+        proto_histogram.set_sample_count(histogram.count());
+        proto_histogram.set_sample_sum(histogram.sum() as f64);
+        proto_histogram.set_bucket(
+            histogram
+                .cumulative()
+                .into_iter()
+                .map(|(bound, count)| {
+                    let mut bucket = proto::Bucket::default();
+                    bucket.set_upper_bound(bound as f64);
+                    bucket.set_cumulative_count(count);
+                    bucket
+                })
+                .collect(),
+        );
+        let mut metric = proto::Metric::default();
+        metric.set_label(labels);
+        metric.set_histogram(proto_histogram);
+        self.metrics.lock().push(metric);
+    }
+}
+
+impl Collector for HistogramFamily {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let metrics = std::mem::take(&mut *self.metrics.lock());
+        if metrics.is_empty() {
+            return Vec::new();
+        }
+        let mut family = MetricFamily::default();
+        family.set_name(self.desc.fq_name.clone());
+        family.set_help(self.desc.help.clone());
+        family.set_field_type(MetricType::HISTOGRAM);
+        family.set_metric(metrics);
+        vec![family]
+    }
+}
+
+/// One scrape's worth of metric families, populated by
+/// [`Analytics::export_to_prometheus`]. Every family is labelled by `source`.
+///
+/// [`Analytics::export_to_prometheus`]: crate::analytics::Analytics::export_to_prometheus
 pub struct PromMetrics {
+    pub parsed_lines: IntCounterVec,
+    pub parse_errors: IntCounterVec,
+    pub redeliveries: IntCounterVec,
+    pub late_events: IntCounterVec,
+    pub clock_skew_hosts: IntGaugeVec,
+    pub response_sizes: HistogramFamily,
+    pub response_sizes_by_class: HistogramFamily,
+    pub response_sizes_by_path: HistogramFamily,
     pub event_counts: IntCounterVec,
+    pub status_class_counts: IntCounterVec,
     pub path_hits: IntCounterVec,
+    pub path_hits_error_bound: IntGaugeVec,
+    pub distinct_hosts_per_path: IntGaugeVec,
+    pub distinct_hosts_per_hour: IntGaugeVec,
+    pub hll_relative_error: GaugeVec,
     pub host_hits: IntCounterVec,
+    pub folded_keys: IntCounterVec,
+    pub referrer_hits: IntCounterVec,
+    pub user_agent_hits: IntCounterVec,
+    pub service_counts: IntCounterVec,
+    pub level_counts: IntCounterVec,
+    pub syslog_severity_counts: IntCounterVec,
+    pub syslog_app_counts: IntCounterVec,
+    pub message_counts: IntCounterVec,
+    pub extra_field_counts: IntCounterVec,
     pub bytes_per_hour_per_host: IntGaugeVec,
-    pub registry: Registry,
+    pub bucket_hits: IntGaugeVec,
+    pub bucket_class_hits: IntGaugeVec,
 }
 
 impl PromMetrics {
     pub fn new() -> Self {
-        let registry = Registry::new();
+        let parsed_lines = IntCounterVec::new(
+            opts!("parsed_lines", "Number of log lines parsed per format"),
+            &["source", "format"],
+        )
+        .unwrap();
+
+        let parse_errors = IntCounterVec::new(
+            opts!(
+                "parse_errors_total",
+                "Number of rejected log lines per format and failing field"
+            ),
+            &["source", "format", "reason"],
+        )
+        .unwrap();
+
+        let redeliveries = IntCounterVec::new(
+            opts!(
+                "redeliveries_total",
+                "Number of messages delivered again because they were not acked in time"
+            ),
+            &["source", "subject"],
+        )
+        .unwrap();
+
+        let late_events = IntCounterVec::new(
+            opts!(
+                "late_events_total",
+                "Number of events too far behind the watermark to be bucketed, see --allowed-lateness"
+            ),
+            &["source"],
+        )
+        .unwrap();
+
+        let clock_skew_hosts = IntGaugeVec::new(
+            opts!(
+                "clock_skew_hosts",
+                "Seconds ahead of hosts past --max-future-skew, see --allowed-lateness for how long they stay"
+            ),
+            &["source", "host"],
+        )
+        .unwrap();
 
-        let event_counts = register_int_counter_vec!(
+        let response_sizes = HistogramFamily::new(
+            "response_size_bytes",
+            "Response sizes, see --size-buckets",
+            &["source"],
+        );
+
+        let response_sizes_by_class = HistogramFamily::new(
+            "response_size_bytes_by_class",
+            "Response sizes per status class, see --size-buckets",
+            &["source", "class"],
+        );
+
+        let response_sizes_by_path = HistogramFamily::new(
+            "response_size_bytes_by_path",
+            "Response sizes per top path, see --size-buckets",
+            &["source", "path"],
+        );
+
+        let event_counts = IntCounterVec::new(
             opts!("event_count", "Number of HTTP status code events"),
-            &["status"]
+            &["source", "status"],
+        )
+        .unwrap();
+
+        let status_class_counts = IntCounterVec::new(
+            opts!(
+                "status_class_count",
+                "Number of HTTP status code events per class"
+            ),
+            &["source", "class"],
         )
         .unwrap();
 
         let path_hits =
-            register_int_counter_vec!(opts!("path_hits", "Hits per path"), &["path"]).unwrap();
+            IntCounterVec::new(opts!("path_hits", "Hits per path"), &["source", "path"]).unwrap();
+
+        let path_hits_error_bound = IntGaugeVec::new(
+            opts!(
+                "path_hits_error_bound",
+                "Most hits a path can have had without showing up in path_hits, and the most path_hits can overcount by"
+            ),
+            &["source"],
+        )
+        .unwrap();
+
+        let distinct_hosts_per_path = IntGaugeVec::new(
+            opts!(
+                "distinct_hosts_per_path",
+                "Estimated number of distinct hosts per path, at the --hll-precision in precision"
+            ),
+            &["source", "precision", "path"],
+        )
+        .unwrap();
+
+        let distinct_hosts_per_hour = IntGaugeVec::new(
+            opts!(
+                "distinct_hosts_per_hour",
+                "Estimated number of distinct hosts per hour, at the --hll-precision in precision"
+            ),
+            &["source", "precision", "hour"],
+        )
+        .unwrap();
+
+        let hll_relative_error = GaugeVec::new(
+            opts!(
+                "hll_relative_error",
+                "Relative standard error of the distinct_hosts estimates, 1.04 / sqrt(2^precision)"
+            ),
+            &["source", "precision"],
+        )
+        .unwrap();
 
         let host_hits =
-            register_int_counter_vec!(opts!("host_hits", "Hits per host"), &["host"]).unwrap();
+            IntCounterVec::new(opts!("host_hits", "Hits per host"), &["source", "host"]).unwrap();
+
+        let folded_keys = IntCounterVec::new(
+            opts!(
+                "folded_keys_total",
+                "Number of keys folded into the other bucket once a dimension hit its cardinality limit"
+            ),
+            &["source", "dimension"],
+        )
+        .unwrap();
+
+        let referrer_hits = IntCounterVec::new(
+            opts!("referrer_hits", "Hits per referrer"),
+            &["source", "referrer"],
+        )
+        .unwrap();
+
+        let user_agent_hits = IntCounterVec::new(
+            opts!("user_agent_hits", "Hits per user-agent family"),
+            &["source", "family"],
+        )
+        .unwrap();
+
+        let service_counts = IntCounterVec::new(
+            opts!(
+                "service_count",
+                "Number of structured log events per service"
+            ),
+            &["source", "service"],
+        )
+        .unwrap();
+
+        let level_counts = IntCounterVec::new(
+            opts!("level_count", "Number of structured log events per level"),
+            &["source", "level"],
+        )
+        .unwrap();
+
+        let syslog_severity_counts = IntCounterVec::new(
+            opts!(
+                "syslog_severity_count",
+                "Number of syslog messages per severity"
+            ),
+            &["source", "severity"],
+        )
+        .unwrap();
+
+        let syslog_app_counts = IntCounterVec::new(
+            opts!("syslog_app_count", "Number of syslog messages per app-name"),
+            &["source", "app"],
+        )
+        .unwrap();
+
+        let message_counts = IntCounterVec::new(
+            opts!(
+                "message_count",
+                "Number of structured log events per message"
+            ),
+            &["source", "message"],
+        )
+        .unwrap();
+
+        let extra_field_counts = IntCounterVec::new(
+            opts!(
+                "extra_field_count",
+                "Number of lines per value of each extra --patterns capture"
+            ),
+            &["source", "field", "value"],
+        )
+        .unwrap();
 
         let bytes_per_hour_per_host = IntGaugeVec::new(
             opts!("host_hour_bytes", "Bytes served per hour per host"),
-            &["host", "hour"],
+            &["source", "host", "hour"],
         )
         .unwrap();
 
-        registry
-            .register(Box::new(bytes_per_hour_per_host.clone()))
-            .unwrap();
-        registry.register(Box::new(event_counts.clone())).unwrap();
-        registry.register(Box::new(path_hits.clone())).unwrap();
-        registry.register(Box::new(host_hits.clone())).unwrap();
+        let bucket_hits = IntGaugeVec::new(
+            opts!("bucket_hits", "Hits per time bucket, see --resolutions"),
+            &["source", "resolution", "bucket"],
+        )
+        .unwrap();
+
+        let bucket_class_hits = IntGaugeVec::new(
+            opts!(
+                "bucket_class_hits",
+                "Hits per time bucket per status class, see --resolutions"
+            ),
+            &["source", "resolution", "bucket", "class"],
+        )
+        .unwrap();
 
         Self {
-            registry,
+            parsed_lines,
+            parse_errors,
+            redeliveries,
+            late_events,
+            clock_skew_hosts,
+            response_sizes,
+            response_sizes_by_class,
+            response_sizes_by_path,
             event_counts,
+            status_class_counts,
             path_hits,
+            path_hits_error_bound,
+            distinct_hosts_per_path,
+            distinct_hosts_per_hour,
+            hll_relative_error,
             host_hits,
+            folded_keys,
+            referrer_hits,
+            user_agent_hits,
+            service_counts,
+            level_counts,
+            syslog_severity_counts,
+            syslog_app_counts,
+            message_counts,
+            extra_field_counts,
             bytes_per_hour_per_host,
+            bucket_hits,
+            bucket_class_hits,
+        }
+    }
+
+    fn collectors(&self) -> Vec<&dyn Collector> {
+        vec![
+            &self.parsed_lines,
+            &self.parse_errors,
+            &self.redeliveries,
+            &self.late_events,
+            &self.clock_skew_hosts,
+            &self.response_sizes,
+            &self.response_sizes_by_class,
+            &self.response_sizes_by_path,
+            &self.event_counts,
+            &self.status_class_counts,
+            &self.path_hits,
+            &self.path_hits_error_bound,
+            &self.distinct_hosts_per_path,
+            &self.distinct_hosts_per_hour,
+            &self.hll_relative_error,
+            &self.host_hits,
+            &self.folded_keys,
+            &self.referrer_hits,
+            &self.user_agent_hits,
+            &self.service_counts,
+            &self.level_counts,
+            &self.syslog_severity_counts,
+            &self.syslog_app_counts,
+            &self.message_counts,
+            &self.extra_field_counts,
+            &self.bytes_per_hour_per_host,
+            &self.bucket_hits,
+            &self.bucket_class_hits,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::TextEncoder;
+
+    fn scrape(registry: &Registry) -> String {
+        let mut buffer = String::new();
+        TextEncoder::new()
+            .encode_utf8(&registry.gather(), &mut buffer)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn scraping_does_not_inflate_counters() {
+        let sources = Arc::new(Sources::default());
+        let analytics = sources.get("logs");
+        analytics.record_event(200);
+        analytics.record_event(200);
+        analytics.record_path("/api");
+        analytics.record_host("10.0.0.1");
+        let registry = registry(sources, None);
+
+        let first = scrape(&registry);
+        let second = scrape(&registry);
+        assert_eq!(first, second);
+        assert!(first.contains("event_count{source=\"logs\",status=\"200\"} 2"));
+        assert!(first.contains("path_hits{path=\"/api\",source=\"logs\"} 1"));
+        assert!(first.contains("host_hits{host=\"10.0.0.1\",source=\"logs\"} 1"));
+
+        analytics.record_event(200);
+        assert!(scrape(&registry).contains("event_count{source=\"logs\",status=\"200\"} 3"));
+    }
+
+    #[test]
+    fn distinct_hosts_carry_their_precision() {
+        let sources = Arc::new(Sources::default());
+        let at = chrono::TimeZone::with_ymd_and_hms(&chrono::Utc, 2024, 1, 1, 12, 0, 0).unwrap();
+        sources.get("logs").record_visit("10.0.0.1", "/", at);
+        let registry = registry(sources, None);
+
+        let scraped = scrape(&registry);
+        assert!(scraped.contains(
+            "distinct_hosts_per_hour{hour=\"2024010112\",precision=\"12\",source=\"logs\"} 1"
+        ));
+        assert!(scraped.contains("hll_relative_error{precision=\"12\",source=\"logs\"} 0.01625"));
+    }
+
+    #[test]
+    fn sources_are_exported_side_by_side() {
+        let sources = Arc::new(Sources::default());
+        sources.get("web").record_event(200);
+        sources.get("api").record_event(200);
+        sources.get("api").record_event(200);
+        let registry = registry(sources, None);
+
+        let scraped = scrape(&registry);
+        assert!(scraped.contains("event_count{source=\"api\",status=\"200\"} 2"));
+        assert!(scraped.contains("event_count{source=\"web\",status=\"200\"} 1"));
+    }
+
+    #[test]
+    fn response_sizes_are_exported_as_histograms() {
+        let sources = Arc::new(Sources::default());
+        let analytics = sources.get("logs");
+        analytics.record_path("/api");
+        analytics.record_response_size("/api", 200, 512);
+        analytics.record_response_size("/api", 503, 50_000);
+        let registry = registry(sources, None);
+
+        let scraped = scrape(&registry);
+        assert!(scraped.contains("# TYPE response_size_bytes histogram"));
+        assert!(scraped.contains("response_size_bytes_bucket{source=\"logs\",le=\"1000\"} 1"));
+        assert!(scraped.contains("response_size_bytes_bucket{source=\"logs\",le=\"+Inf\"} 2"));
+        assert!(scraped.contains("response_size_bytes_sum{source=\"logs\"} 50512"));
+        assert!(
+            scraped.contains("response_size_bytes_by_class_count{class=\"5xx\",source=\"logs\"} 1")
+        );
+        assert!(
+            scraped.contains("response_size_bytes_by_path_count{path=\"/api\",source=\"logs\"} 2")
+        );
+    }
+
+    #[test]
+    fn replica_is_added_to_every_series() {
+        let sources = Arc::new(Sources::default());
+        let analytics = sources.get("logs");
+        analytics.record_event(200);
+        analytics.record_host("10.0.0.1");
+        let registry = registry(sources, Some("log-analyzer-0".into()));
+
+        let families = registry.gather();
+        assert!(!families.is_empty());
+        for family in &families {
+            for metric in family.get_metric() {
+                assert!(
+                    metric
+                        .get_label()
+                        .iter()
+                        .any(|label| label.name() == "replica"
+                            && label.value() == "log-analyzer-0"),
+                    "{} has no replica label",
+                    family.name()
+                );
+            }
         }
     }
 }
@@ -0,0 +1,216 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, Write},
+};
+
+use clap::ValueEnum;
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+use crate::analytics::Analytics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Table,
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Count {
+    pub key: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HostHourBytes {
+    pub host: String,
+    pub hour: String,
+    pub bytes: u64,
+}
+
+/// What `analyze` prints for one source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Report {
+    pub source: String,
+    pub parsed: usize,
+    pub rejected: usize,
+    pub status: BTreeMap<u16, usize>,
+    pub top_paths: Vec<Count>,
+    pub top_hosts: Vec<Count>,
+    pub bytes_per_hour_per_host: Vec<HostHourBytes>,
+}
+
+impl Report {
+    pub fn new(source: &str, analytics: &Analytics, top: usize) -> Self {
+        let counts = |entries: Vec<(String, usize)>| {
+            entries
+                .into_iter()
+                .map(|(key, count)| Count { key, count })
+                .collect()
+        };
+        let mut bytes_per_hour_per_host: Vec<_> = analytics
+            .bytes_per_hour_per_host()
+            .into_iter()
+            .flat_map(|(host, by_hour)| {
+                by_hour.into_iter().map(move |(hour, bytes)| HostHourBytes {
+                    host: host.clone(),
+                    hour: hour.into_utc().format("%Y-%m-%dT%H:00Z").to_string(),
+                    bytes,
+                })
+            })
+            .collect();
+        bytes_per_hour_per_host
+            .sort_unstable_by(|a, b| (&a.host, &a.hour).cmp(&(&b.host, &b.hour)));
+        Self {
+            source: source.to_owned(),
+            parsed: analytics.parsed_frequency().values().sum(),
+            rejected: analytics.parse_error_frequency().values().sum(),
+            status: analytics.event_frequency().into_iter().collect(),
+            top_paths: counts(analytics.top_path_frequency(top)),
+            top_hosts: counts(analytics.top_host_frequency(top)),
+            bytes_per_hour_per_host,
+        }
+    }
+}
+
+pub fn write(reports: &[Report], format: ReportFormat, out: &mut impl Write) -> io::Result<()> {
+    match format {
+        ReportFormat::Table => write_table(reports, out),
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, reports)?;
+            writeln!(out)
+        }
+        ReportFormat::Csv => write_csv(reports, out),
+    }
+}
+
+fn write_table(reports: &[Report], out: &mut impl Write) -> io::Result<()> {
+    let n = |count: usize| count.to_formatted_string(&Locale::en);
+    for report in reports {
+        writeln!(out, "== {} ==", report.source)?;
+        writeln!(
+            out,
+            "{} lines parsed, {} rejected",
+            n(report.parsed),
+            n(report.rejected)
+        )?;
+
+        writeln!(out, "\n{:<8} {:>12}", "STATUS", "COUNT")?;
+        for (status, count) in &report.status {
+            writeln!(out, "{status:<8} {:>12}", n(*count))?;
+        }
+
+        for (title, entries) in [("PATH", &report.top_paths), ("HOST", &report.top_hosts)] {
+            let width = entries
+                .iter()
+                .map(|c| c.key.len())
+                .max()
+                .unwrap_or(0)
+                .max(title.len());
+            writeln!(out, "\n{title:<width$} {:>12}", "COUNT")?;
+            for Count { key, count } in entries {
+                writeln!(out, "{key:<width$} {:>12}", n(*count))?;
+            }
+        }
+
+        let width = report
+            .bytes_per_hour_per_host
+            .iter()
+            .map(|b| b.host.len())
+            .max()
+            .unwrap_or(0)
+            .max("HOST".len());
+        writeln!(out, "\n{:<width$} {:<17} {:>15}", "HOST", "HOUR", "BYTES")?;
+        for HostHourBytes { host, hour, bytes } in &report.bytes_per_hour_per_host {
+            writeln!(
+                out,
+                "{host:<width$} {hour:<17} {:>15}",
+                bytes.to_formatted_string(&Locale::en)
+            )?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+/// One row per value: `source,report,key,hour,value`, `hour` only being set
+/// for `bytes_per_hour`.
+fn write_csv(reports: &[Report], out: &mut impl Write) -> io::Result<()> {
+    writeln!(out, "source,report,key,hour,value")?;
+    for report in reports {
+        let source = csv_field(&report.source);
+        writeln!(out, "{source},parsed,,,{}", report.parsed)?;
+        writeln!(out, "{source},rejected,,,{}", report.rejected)?;
+        for (status, count) in &report.status {
+            writeln!(out, "{source},status,{status},,{count}")?;
+        }
+        for Count { key, count } in &report.top_paths {
+            writeln!(out, "{source},path,{},,{count}", csv_field(key))?;
+        }
+        for Count { key, count } in &report.top_hosts {
+            writeln!(out, "{source},host,{},,{count}", csv_field(key))?;
+        }
+        for HostHourBytes { host, hour, bytes } in &report.bytes_per_hour_per_host {
+            writeln!(
+                out,
+                "{source},bytes_per_hour,{},{hour},{bytes}",
+                csv_field(host)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use chrono::{TimeZone, Utc};
+
+    fn report() -> Report {
+        let analytics = Analytics::default();
+        let hour = Utc.with_ymd_and_hms(2025, 7, 25, 23, 59, 59).unwrap();
+        analytics.record_event(200);
+        analytics.record_event(200);
+        analytics.record_event(404);
+        analytics.record_path("/search?q=a,b");
+        analytics.record_host("10.0.0.1");
+        analytics.record_host_hour_bytes("10.0.0.1", hour, 512);
+        Report::new("access.log", &analytics, 10)
+    }
+
+    #[test]
+    fn report_collects_analytics() {
+        let report = report();
+        assert_that!(report.status).is_equal_to(BTreeMap::from([(200, 2), (404, 1)]));
+        assert_that!(report.top_hosts).is_equal_to(vec![Count {
+            key: "10.0.0.1".into(),
+            count: 1,
+        }]);
+        assert_that!(report.bytes_per_hour_per_host).is_equal_to(vec![HostHourBytes {
+            host: "10.0.0.1".into(),
+            hour: "2025-07-25T23:00Z".into(),
+            bytes: 512,
+        }]);
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let mut out = Vec::new();
+        write(&[report()], ReportFormat::Csv, &mut out).unwrap();
+        let csv = String::from_utf8(out).unwrap();
+        assert_that!(csv.lines().next()).is_equal_to(Some("source,report,key,hour,value"));
+        assert!(csv.contains("access.log,status,404,,1\n"));
+        assert!(csv.contains("access.log,path,\"/search?q=a,b\",,1\n"));
+        assert!(csv.contains("access.log,bytes_per_hour,10.0.0.1,2025-07-25T23:00Z,512\n"));
+    }
+}
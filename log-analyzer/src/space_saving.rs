@@ -0,0 +1,134 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    hash::Hash,
+};
+
+/// Space-Saving heavy hitters (Metwally et al.) over at most `capacity`
+/// counters.
+///
+/// Every key whose true count exceeds `total / capacity` is monitored. A
+/// monitored key's count overestimates its true count by at most its
+/// `error`, and no unmonitored key has been seen more than
+/// [`Self::error_bound`] times.
+#[derive(Debug)]
+pub struct SpaceSaving<K> {
+    capacity: usize,
+    counters: HashMap<K, Estimate>,
+    by_count: BTreeSet<(usize, K)>,
+    /// Whether a counter has ever been taken over.
+    evicted: bool,
+}
+
+/// A monitored key's count, at most `error` above its true count.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Estimate {
+    pub count: usize,
+    pub error: usize,
+}
+
+impl<K: Hash + Ord + Clone> SpaceSaving<K> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Space-Saving needs at least one counter");
+        Self {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+            by_count: BTreeSet::new(),
+            evicted: false,
+        }
+    }
+
+    /// Counts `key`, returning the key whose counter it took over, if any.
+    pub fn insert(&mut self, key: K) -> Option<K> {
+        if let Some(estimate) = self.counters.get_mut(&key) {
+            self.by_count.remove(&(estimate.count, key.clone()));
+            estimate.count += 1;
+            self.by_count.insert((estimate.count, key));
+            return None;
+        }
+        let mut taken_over = None;
+        let estimate = if self.counters.len() < self.capacity {
+            Estimate { count: 1, error: 0 }
+        } else {
+            // Take over the smallest counter, inheriting its count as error.
+            let (min, evicted) = self.by_count.pop_first()?;
+            self.counters.remove(&evicted);
+            self.evicted = true;
+            taken_over = Some(evicted);
+            Estimate {
+                count: min + 1,
+                error: min,
+            }
+        };
+        self.by_count.insert((estimate.count, key.clone()));
+        self.counters.insert(key, estimate);
+        taken_over
+    }
+
+    pub fn contains(&self, key: &K) -> bool {
+        self.counters.contains_key(key)
+    }
+
+    /// The `n` largest estimates, largest first.
+    pub fn top(&self, n: usize) -> Vec<(K, Estimate)> {
+        self.by_count
+            .iter()
+            .rev()
+            .take(n)
+            .map(|(_, key)| (key.clone(), self.counters[key]))
+            .collect()
+    }
+
+    /// Most times any key that is not monitored can have been seen.
+    pub fn error_bound(&self) -> usize {
+        if self.evicted {
+            self.by_count.first().map_or(0, |(min, _)| *min)
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+
+    #[test]
+    fn counts_are_exact_below_capacity() {
+        let mut sketch = SpaceSaving::new(3);
+        for key in ["a", "b", "a", "c", "a", "b"] {
+            sketch.insert(key);
+        }
+        assert_that!(sketch.top(2)).is_equal_to(vec![
+            ("a", Estimate { count: 3, error: 0 }),
+            ("b", Estimate { count: 2, error: 0 }),
+        ]);
+        assert_that!(sketch.error_bound()).is_equal_to(0);
+    }
+
+    #[test]
+    fn heavy_hitters_survive_a_burst_of_one_offs() {
+        let mut sketch = SpaceSaving::new(10);
+        for _ in 0..500 {
+            sketch.insert("/popular".to_owned());
+        }
+        for i in 0..1_000 {
+            sketch.insert(format!("/once/{i}"));
+        }
+        for _ in 0..100 {
+            sketch.insert("/popular".to_owned());
+        }
+
+        let (key, estimate) = sketch.top(1).remove(0);
+        assert_that!(key).is_equal_to("/popular".to_owned());
+        assert_that!(estimate).is_equal_to(Estimate {
+            count: 600,
+            error: 0,
+        });
+        // Overestimation never exceeds total / capacity.
+        assert_that!(sketch.error_bound()).is_at_most(1_600 / 10);
+        for (_, Estimate { error, .. }) in sketch.top(10) {
+            assert_that!(error).is_at_most(sketch.error_bound());
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::{collections::BTreeMap, fmt, str::FromStr};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::invariants::Timestamp;
+
+/// Bucket width and how many of the most recent buckets are kept, written
+/// `WIDTH:BUCKETS` on the command line, e.g. `1m:60` or `1d:7`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: TimeDelta,
+    pub retention: usize,
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (width, retention) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected WIDTH:BUCKETS, got `{s}`"))?;
+        let width = parse_duration(width)?;
+        let retention = retention
+            .parse()
+            .ok()
+            .filter(|retention| *retention > 0)
+            .ok_or_else(|| format!("`{retention}` is not a positive number of buckets"))?;
+        Ok(Self { width, retention })
+    }
+}
+
+/// Parses a positive duration such as `90s`, `15m`, `1h` or `7d`.
+pub fn parse_duration(s: &str) -> Result<TimeDelta, String> {
+    let unit = match s.chars().last() {
+        Some('s') => TimeDelta::seconds(1),
+        Some('m') => TimeDelta::minutes(1),
+        Some('h') => TimeDelta::hours(1),
+        Some('d') => TimeDelta::days(1),
+        _ => return Err(format!("`{s}` needs a unit of s, m, h or d")),
+    };
+    let count: i32 = s[..s.len() - 1]
+        .parse()
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(|| format!("`{s}` is not a positive duration"))?;
+    Ok(unit * count)
+}
+
+/// The bucket starting at `start`, created by `new` if needed. When that would
+/// exceed `retention`, the oldest bucket is dropped to make room, unless
+/// `start` is older still, in which case there is no bucket for it.
+pub fn bucket_mut<V>(
+    buckets: &mut BTreeMap<Timestamp, V>,
+    start: Timestamp,
+    retention: usize,
+    new: impl FnOnce() -> V,
+) -> Option<&mut V> {
+    if !buckets.contains_key(&start) && buckets.len() >= retention {
+        if buckets
+            .first_key_value()
+            .is_some_and(|(oldest, _)| start < *oldest)
+        {
+            return None;
+        }
+        buckets.pop_first();
+    }
+    Some(buckets.entry(start).or_insert_with(new))
+}
+
+/// Checks that `resolutions` go from finest to coarsest, each width a
+/// multiple of the one before it, so a coarser bucket always covers whole
+/// finer buckets and is exactly their sum.
+pub fn check_nested(resolutions: &[Resolution]) -> Result<(), String> {
+    for pair in resolutions.windows(2) {
+        let [finer, coarser] = pair else {
+            continue;
+        };
+        if coarser.width <= finer.width
+            || coarser.width.num_seconds() % finer.width.num_seconds() != 0
+        {
+            return Err(format!(
+                "{coarser} is not a coarser multiple of {finer}, resolutions go from finest to coarsest"
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Resolution {
+    /// The width alone, in the largest unit that divides it.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.width.num_seconds();
+        match [(86_400, 'd'), (3_600, 'h'), (60, 'm')]
+            .into_iter()
+            .find(|(unit, _)| secs % unit == 0)
+        {
+            Some((unit, suffix)) => write!(f, "{}{suffix}", secs / unit),
+            None => write!(f, "{secs}s"),
+        }
+    }
+}
+
+/// The same series at several resolutions, nested as [`check_nested`] asks.
+/// Every value is added to each of them, so coarser series are the finer ones
+/// summed but kept for longer.
+#[derive(Debug)]
+pub struct TimeSeries<V> {
+    series: Vec<(Resolution, BTreeMap<Timestamp, V>)>,
+}
+
+impl<V: Default> TimeSeries<V> {
+    pub fn new(resolutions: &[Resolution]) -> Self {
+        Self {
+            series: resolutions.iter().map(|r| (*r, BTreeMap::new())).collect(),
+        }
+    }
+
+    /// Applies `f` to the bucket holding `at` in every resolution that still
+    /// has one, see [`bucket_mut`].
+    pub fn record(&mut self, at: DateTime<Utc>, f: impl Fn(&mut V)) {
+        for (resolution, buckets) in &mut self.series {
+            let start = Timestamp::bucket(at, resolution.width);
+            if let Some(bucket) = bucket_mut(buckets, start, resolution.retention, V::default) {
+                f(bucket);
+            }
+        }
+    }
+
+    /// Every resolution with its buckets, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (Resolution, &BTreeMap<Timestamp, V>)> {
+        self.series
+            .iter()
+            .map(|(resolution, buckets)| (*resolution, buckets))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use asserting::prelude::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parses_and_displays_resolutions() {
+        let resolution: Resolution = "90m:4".parse().unwrap();
+        assert_that!(resolution.width).is_equal_to(TimeDelta::minutes(90));
+        assert_that!(resolution.retention).is_equal_to(4);
+        assert_that!(resolution.to_string()).is_equal_to("90m".to_owned());
+        assert_that!("24h:7".parse::<Resolution>().unwrap().to_string())
+            .is_equal_to("1d".to_owned());
+        assert_that!("1w:7".parse::<Resolution>()).is_err();
+        assert_that!("1m:0".parse::<Resolution>()).is_err();
+    }
+
+    #[test]
+    fn resolutions_must_nest_from_finest_to_coarsest() {
+        let parse = |list: &[&str]| -> Vec<Resolution> {
+            list.iter().map(|r| r.parse().unwrap()).collect()
+        };
+        assert_that!(check_nested(&parse(&["1m:60", "1h:6", "1d:7"]))).is_ok();
+        assert_that!(check_nested(&parse(&["1m:60", "60s:6"]))).is_err();
+        assert_that!(check_nested(&parse(&["1h:6", "1m:60"]))).is_err();
+        assert_that!(check_nested(&parse(&["1m:60", "90s:6"]))).is_err();
+    }
+
+    #[test]
+    fn records_every_resolution_within_its_retention() {
+        let mut series =
+            TimeSeries::<usize>::new(&["1m:2".parse().unwrap(), "1h:2".parse().unwrap()]);
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 12, 0, 30).unwrap();
+        for minute in [0, 1, 2, 0] {
+            series.record(start + TimeDelta::minutes(minute), |n| *n += 1);
+        }
+
+        let buckets: Vec<_> = series
+            .iter()
+            .map(|(resolution, buckets)| {
+                let buckets: Vec<_> = buckets
+                    .iter()
+                    .map(|(ts, n)| (ts.into_utc().format("%H:%M").to_string(), *n))
+                    .collect();
+                (resolution.to_string(), buckets)
+            })
+            .collect();
+        assert_that!(buckets).is_equal_to(vec![
+            (
+                "1m".to_owned(),
+                vec![("12:01".to_owned(), 1), ("12:02".to_owned(), 1)],
+            ),
+            ("1h".to_owned(), vec![("12:00".to_owned(), 4)]),
+        ]);
+    }
+}
@@ -1,143 +1,256 @@
-use crate::models::LogEntry;
-use chrono::{DateTime, TimeDelta, TimeZone, Utc};
-use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::time::{Duration, sleep};
+use crate::{
+    ingest::{self, Ack, Chunk, DeadLetter},
+    models::{LogEntry, LogRecord, Severity, StructuredLog, SyslogEnvelope},
+    parser::{LogFormat, LogParser, ParseError},
+};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use tokio::sync::mpsc::{Receiver, Sender, error::TrySendError};
+use tokio::time::{Duration, Instant, MissedTickBehavior, interval_at};
 use tracing::debug;
 
 #[derive(Debug)]
 pub enum Metric {
+    Parsed(LogFormat),
+    ParseError {
+        format: LogFormat,
+        reason: ParseError,
+        line: String,
+    },
+    Redelivered(String),
+    SyslogSeverity(Severity),
+    SyslogApp(String),
     Event(u16),
     Path(String),
     Host(String),
-    Hit(DateTime<Utc>),
+    Referrer(String),
+    UserAgent(String),
+    Service(String),
+    Level(String),
+    Message(String),
+    Extra {
+        field: String,
+        value: String,
+    },
+    Hit {
+        host: String,
+        timestamp: DateTime<Utc>,
+        status: u16,
+    },
+    Visit {
+        host: String,
+        path: String,
+        timestamp: DateTime<Utc>,
+    },
     HostBytes {
         host: String,
         timestamp: DateTime<Utc>,
         bytes: u64,
     },
+    ResponseSize {
+        path: String,
+        status: u16,
+        bytes: u64,
+    },
+}
+
+/// Metrics ready for the aggregator grouped by source, with the acks of
+/// every chunk they complete. Acks are only sent once the metrics have been
+/// applied.
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub metrics: HashMap<String, Vec<Metric>>,
+    pub acks: Vec<Ack>,
 }
 
-const FLUSH_INTERVAL: Duration = Duration::from_secs(3);
+/// How often buffered metrics and their acks are handed on, however busy
+/// the input is.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(3);
 const BUFFER_SIZE: usize = 1_000_000;
 
-pub async fn worker_loop(tx: Sender<Vec<Metric>>, mut rx: Receiver<String>) {
-    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
+pub async fn worker_loop(
+    tx: Sender<Batch>,
+    mut rx: Receiver<Chunk>,
+    parser: Box<dyn LogParser>,
+    source_tokens: Vec<usize>,
+    mut dead_letters: Option<Sender<DeadLetter>>,
+) {
+    let mut buffer: HashMap<String, Vec<Metric>> = HashMap::new();
+    let mut buffered = 0;
+    let mut acks = Vec::new();
+    let mut flush = interval_at(Instant::now() + FLUSH_INTERVAL, FLUSH_INTERVAL);
+    flush.set_missed_tick_behavior(MissedTickBehavior::Delay);
     loop {
         tokio::select! {
             maybe_chunk = rx.recv() => {
                 match maybe_chunk {
-                    Some(chunk) => {
-                        debug!("chunk: {chunk}");
-                        for line in chunk.split('\n').filter(|l| !l.is_empty()) {
-                            if let Some(LogEntry { status, host, timestamp, path, bytes }) = parse_log_line(line) {
-                                buffer.push(Metric::Event(status));
-                                buffer.push(Metric::Path(path));
-                                buffer.push(Metric::Host(host.clone()));
-                                buffer.push(Metric::Hit(timestamp));
-                                buffer.push(Metric::HostBytes { host, timestamp, bytes });
-                                if buffer.len() >= BUFFER_SIZE {
-                                    tx.send(buffer.split_off(0)).await.ok();
+                    Some(Chunk { subject, payload, redelivered, syslog, ack }) => {
+                        debug!("chunk from {subject}: {payload}");
+                        let source = ingest::source(&subject, &source_tokens);
+                        let mut metrics = buffer.remove(&source).unwrap_or_default();
+                        buffered -= metrics.len();
+                        if redelivered {
+                            metrics.push(Metric::Redelivered(subject.clone()));
+                        }
+                        if let Some(SyslogEnvelope { severity, app_name, .. }) = syslog {
+                            metrics.push(Metric::SyslogSeverity(severity));
+                            metrics.extend(app_name.map(Metric::SyslogApp));
+                        }
+                        for line in payload.split('\n').filter(|l| !l.is_empty()) {
+                            let (format, record) = parser.parse(line);
+                            match record {
+                                Ok(record) => {
+                                    metrics.push(Metric::Parsed(format));
+                                    push_metrics(&mut metrics, record);
                                 }
+                                Err(reason) => {
+                                    metrics.push(Metric::ParseError {
+                                        format,
+                                        reason,
+                                        line: line.to_owned(),
+                                    });
+                                    if let Some(tx) = &dead_letters {
+                                        let letter = DeadLetter {
+                                            subject: subject.clone(),
+                                            reason,
+                                            line: line.to_owned(),
+                                        };
+                                        // Republishing is best effort, it never holds up ingest.
+                                        match tx.try_send(letter) {
+                                            Ok(()) => {}
+                                            Err(TrySendError::Full(_)) => {
+                                                debug!("Dead letter queue is full, dropping a rejected line");
+                                            }
+                                            Err(TrySendError::Closed(_)) => dead_letters = None,
+                                        }
+                                    }
+                                }
+                            }
+                            if buffered + metrics.len() >= BUFFER_SIZE {
+                                buffer.insert(source.clone(), metrics.split_off(0));
+                                let batch = Batch { metrics: std::mem::take(&mut buffer), acks: acks.split_off(0) };
+                                buffered = 0;
+                                tx.send(batch).await.ok();
                             }
                         }
+                        buffered += metrics.len();
+                        buffer.insert(source, metrics);
+                        acks.extend(ack);
+                    }
+                    None => {
+                        if buffered > 0 || !acks.is_empty() {
+                            let batch = Batch { metrics: buffer, acks };
+                            tx.send(batch).await.ok();
+                        }
+                        break;
                     }
-                    None => break,
                 }
             }
-            _ = sleep(FLUSH_INTERVAL) => {
-                if !buffer.is_empty() {
-                    tx.send(buffer.split_off(0)).await.ok();
+            _ = flush.tick() => {
+                if buffered > 0 || !acks.is_empty() {
+                    let batch = Batch { metrics: std::mem::take(&mut buffer), acks: acks.split_off(0) };
+                    buffered = 0;
+                    tx.send(batch).await.ok();
                 }
             }
         }
     }
 }
 
-fn parse_log_line(line: &str) -> Option<LogEntry> {
-    let mut parts = line.split_ascii_whitespace();
-    let host = parts.next()?;
-    parts.next()?; // skip '-'
-    parts.next()?; // skip '-'
-    let ts1 = parts.next()?.strip_prefix('[')?;
-    let ts2 = parts.next()?.strip_suffix(']')?;
-    let ts_start = ts1.as_ptr() as usize - line.as_ptr() as usize;
-    let ts_end = ts2.as_ptr() as usize - line.as_ptr() as usize + ts2.len();
-    let ts_combined = &line[ts_start..ts_end];
-    parts.next()?; // skip method
-    let mut path = parts.next()?;
-    if let Some(stripped) = path.strip_suffix('"') {
-        path = stripped;
+fn push_metrics(buffer: &mut Vec<Metric>, record: LogRecord) {
+    match record {
+        LogRecord::Access(LogEntry {
+            status,
+            host,
+            timestamp,
+            path,
+            bytes,
+            referrer,
+            user_agent,
+            extra,
+            ..
+        }) => {
+            buffer.push(Metric::Event(status));
+            buffer.push(Metric::Path(path.clone()));
+            buffer.push(Metric::Host(host.clone()));
+            // After `Path`, so the path is already monitored.
+            buffer.push(Metric::ResponseSize {
+                path: path.clone(),
+                status,
+                bytes,
+            });
+            buffer.push(Metric::Visit {
+                host: host.clone(),
+                path,
+                timestamp,
+            });
+            if let Some(referrer) = referrer {
+                buffer.push(Metric::Referrer(referrer));
+            }
+            if let Some(user_agent) = user_agent {
+                buffer.push(Metric::UserAgent(user_agent));
+            }
+            buffer.extend(
+                extra
+                    .into_iter()
+                    .map(|(field, value)| Metric::Extra { field, value }),
+            );
+            buffer.push(Metric::Hit {
+                host: host.clone(),
+                timestamp,
+                status,
+            });
+            buffer.push(Metric::HostBytes {
+                host,
+                timestamp,
+                bytes,
+            });
+        }
+        LogRecord::Structured(StructuredLog {
+            service,
+            level,
+            message,
+            ..
+        }) => {
+            buffer.push(Metric::Service(service));
+            buffer.push(Metric::Level(level));
+            buffer.push(Metric::Message(message));
+        }
     }
-    let status: u16 = parts.next()?.parse().ok()?;
-    let bytes = match parts.next()? {
-        "-" => 0,
-        s => s.parse().ok()?,
-    };
-    let dt = parse_apache_timestamp(ts_combined)?;
-    Some(LogEntry {
-        host: host.to_owned(),
-        timestamp: dt,
-        path: path.to_owned(),
-        status,
-        bytes,
-    })
-}
-
-fn parse_apache_timestamp(ts: &str) -> Option<DateTime<Utc>> {
-    let mut parts = ts.splitn(7, ['/', ':', ' ']);
-    let day: u32 = parts.next()?.parse().ok()?;
-    let month = match parts.next()? {
-        "Jan" => 1,
-        "Feb" => 2,
-        "Mar" => 3,
-        "Apr" => 4,
-        "May" => 5,
-        "Jun" => 6,
-        "Jul" => 7,
-        "Aug" => 8,
-        "Sep" => 9,
-        "Oct" => 10,
-        "Nov" => 11,
-        "Dec" => 12,
-        _ => return None,
-    };
-    let year: i32 = parts.next()?.parse().ok()?;
-    let hour: u32 = parts.next()?.parse().ok()?;
-    let min: u32 = parts.next()?.parse().ok()?;
-    let sec: u32 = parts.next()?.parse().ok()?;
-    let offset: i32 = parts.next().map(|s| s.parse::<i32>().ok())??;
-    let offset_hours = offset / 100;
-    let offset_min = offset - (offset_hours * 100);
-    Utc.with_ymd_and_hms(year, month, day, hour, min, sec)
-        .single()
-        .map(|t| t - TimeDelta::hours(offset_hours.into()) - TimeDelta::minutes(offset_min.into()))
 }
 
 #[cfg(test)]
-mod test {
+mod tests {
     use super::*;
-    use asserting::{expectations::IsEqualTo, prelude::*};
-    use chrono::prelude::*;
+    use asserting::prelude::*;
+    use tokio::{
+        sync::mpsc,
+        time::{sleep, timeout},
+    };
 
-    #[test]
-    fn parse_log_line_valid() {
-        let line =
-            r#"202.32.92.47 - - [01/Jun/1995:00:00:59 -0600] "GET /~scottp/publish.html" 200 271"#;
-        assert_that!(parse_log_line(line))
-            .is_some()
-            .mapping(|o| o.unwrap())
-            .expecting(IsEqualTo {
-                expected: LogEntry {
-                    host: "202.32.92.47".into(),
-                    timestamp: FixedOffset::west_opt(6 * 3600)
-                        .unwrap()
-                        .with_ymd_and_hms(1995, 6, 1, 0, 0, 59)
-                        .unwrap()
-                        .with_timezone(&Utc),
-                    path: "/~scottp/publish.html".into(),
-                    status: 200,
-                    bytes: 271,
-                },
-            });
+    #[tokio::test]
+    async fn flushes_on_schedule_while_chunks_keep_coming() {
+        let (chunk_tx, chunk_rx) = mpsc::channel(1);
+        let (batch_tx, mut batch_rx) = mpsc::channel(1);
+        let parser = LogFormat::Apache.parser(None).unwrap();
+        tokio::spawn(worker_loop(batch_tx, chunk_rx, parser, vec![], None));
+        tokio::spawn(async move {
+            loop {
+                let chunk = Chunk {
+                    subject: "logs".into(),
+                    payload: "not a log line\n".into(),
+                    redelivered: false,
+                    syslog: None,
+                    ack: None,
+                };
+                if chunk_tx.send(chunk).await.is_err() {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+        });
+
+        let batch = timeout(FLUSH_INTERVAL * 2, batch_rx.recv()).await;
+        assert_that!(batch.ok().flatten().is_some()).is_true();
     }
 }
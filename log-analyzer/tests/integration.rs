@@ -1,6 +1,8 @@
+use async_nats::jetstream::{self, consumer::PullConsumer};
+use futures_util::StreamExt;
 use std::time::Duration;
-use testcontainers::runners::AsyncRunner;
-use testcontainers_modules::nats;
+use testcontainers::{ImageExt, runners::AsyncRunner};
+use testcontainers_modules::nats::{self, NatsServerCmd};
 use tokio::{process::Command, time::sleep};
 
 #[ignore]
@@ -83,3 +85,301 @@ async fn log_analyzer_starts_and_consumes() {
     let _ = child.kill().await;
     let _ = child.wait().await;
 }
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn log_analyzer_republishes_rejected_lines() {
+    // Start NATS container
+    let nats_container = nats::Nats::default()
+        .start()
+        .await
+        .expect("Failed to start NATS container");
+    let nats_port = nats_container
+        .get_host_port_ipv4(4222)
+        .await
+        .expect("Failed to get NATS port");
+    let nats_url = format!("nats://127.0.0.1:{nats_port}");
+
+    let nats_client = async_nats::connect(&nats_url)
+        .await
+        .expect("Failed to connect to NATS");
+    let mut dead_letters = nats_client
+        .subscribe("logs.rejected")
+        .await
+        .expect("Failed to subscribe to dead letters");
+
+    // Start log-analyzer
+    let metrics_port = portpicker::pick_unused_port().expect("No free ports available");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-analyzer"))
+        .args([
+            "--nats-url",
+            &nats_url,
+            "--subject",
+            "logs",
+            "--dead-letter-subject",
+            "logs.rejected",
+            "--port",
+            &metrics_port.to_string(),
+        ])
+        .spawn()
+        .expect("Failed to start log-analyzer");
+    sleep(Duration::from_secs(2)).await;
+
+    // Publish one good and one bad line
+    let payload = concat!(
+        r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#,
+        "\n",
+        r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" OK 512"#,
+    );
+    nats_client.publish("logs", payload.into()).await.unwrap();
+    nats_client.flush().await.unwrap();
+
+    let message = tokio::time::timeout(Duration::from_secs(10), dead_letters.next())
+        .await
+        .expect("No dead letter published")
+        .expect("Dead letter subscription closed");
+    let headers = message.headers.expect("Dead letter has no headers");
+    assert_eq!(
+        headers.get("Log-Analyzer-Reason").map(|v| v.as_str()),
+        Some("status")
+    );
+    assert_eq!(
+        headers.get("Log-Analyzer-Subject").map(|v| v.as_str()),
+        Some("logs")
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&message.payload),
+        concat!(
+            r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" OK 512"#,
+            "\n"
+        )
+    );
+
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn log_analyzer_acks_jetstream_after_aggregation() {
+    // Start NATS container with JetStream enabled
+    let nats_container = nats::Nats::default()
+        .with_cmd(&NatsServerCmd::default().with_jetstream())
+        .start()
+        .await
+        .expect("Failed to start NATS container");
+    let nats_port = nats_container
+        .get_host_port_ipv4(4222)
+        .await
+        .expect("Failed to get NATS port");
+    let nats_url = format!("nats://127.0.0.1:{nats_port}");
+
+    // Persist a log before the analyzer is running
+    let context = jetstream::new(
+        async_nats::connect(&nats_url)
+            .await
+            .expect("Failed to connect to NATS"),
+    );
+    let stream = context
+        .get_or_create_stream(jetstream::stream::Config {
+            name: "LOGS".into(),
+            subjects: vec!["logs".into()],
+            ..Default::default()
+        })
+        .await
+        .expect("Failed to create stream");
+    let apache_log = r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#;
+    context
+        .publish("logs", apache_log.into())
+        .await
+        .unwrap()
+        .await
+        .unwrap();
+
+    // Start log-analyzer
+    let metrics_port = portpicker::pick_unused_port().expect("No free ports available");
+    let metrics_url = format!("http://localhost:{metrics_port}");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-analyzer"))
+        .args([
+            "--input",
+            "jetstream",
+            "--nats-url",
+            &nats_url,
+            "--subject",
+            "logs",
+            "--port",
+            &metrics_port.to_string(),
+        ])
+        .spawn()
+        .expect("Failed to start log-analyzer");
+
+    // Poll /metrics for expected content
+    let client = reqwest::Client::new();
+    let mut metrics_ok = false;
+    for _ in 0..50 {
+        let resp = client
+            .get(format!("{metrics_url}/metrics"))
+            .send()
+            .await
+            .map(|r| r.text());
+        if let Ok(text) = resp
+            && text.await.unwrap_or_default().contains("event_count")
+        {
+            metrics_ok = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(metrics_ok, "Metrics endpoint never returned event_count");
+
+    // The durable consumer has nothing left waiting for an ack
+    let mut consumer: PullConsumer = stream
+        .get_consumer("log-analyzer")
+        .await
+        .expect("Durable consumer was not created");
+    let mut acked = false;
+    for _ in 0..20 {
+        let info = consumer.info().await.unwrap();
+        if info.ack_floor.stream_sequence == 1 && info.num_ack_pending == 0 {
+            acked = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(acked, "JetStream message was never acked");
+
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn log_analyzer_labels_wildcard_subjects_by_source() {
+    // Start NATS container
+    let nats_container = nats::Nats::default()
+        .start()
+        .await
+        .expect("Failed to start NATS container");
+    let nats_port = nats_container
+        .get_host_port_ipv4(4222)
+        .await
+        .expect("Failed to get NATS port");
+    let nats_url = format!("nats://127.0.0.1:{nats_port}");
+
+    // Start log-analyzer on every `logs.<service>.<env>` subject
+    let metrics_port = portpicker::pick_unused_port().expect("No free ports available");
+    let metrics_url = format!("http://localhost:{metrics_port}");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-analyzer"))
+        .args([
+            "--nats-url",
+            &nats_url,
+            "--subject",
+            "logs.>",
+            "--source-tokens",
+            "1",
+            "--port",
+            &metrics_port.to_string(),
+        ])
+        .spawn()
+        .expect("Failed to start log-analyzer");
+    sleep(Duration::from_secs(2)).await;
+
+    let nats_client = async_nats::connect(&nats_url)
+        .await
+        .expect("Failed to connect to NATS");
+    let apache_log = r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#;
+    for subject in ["logs.web.prod", "logs.api.prod", "logs.api.dev"] {
+        nats_client
+            .publish(subject, apache_log.into())
+            .await
+            .unwrap();
+    }
+    nats_client.flush().await.unwrap();
+
+    // Poll /metrics until both sources are reported
+    let client = reqwest::Client::new();
+    let mut metrics_ok = false;
+    for _ in 0..50 {
+        let resp = client
+            .get(format!("{metrics_url}/metrics"))
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+
+        if resp.contains(r#"event_count{source="api",status="200"} 2"#)
+            && resp.contains(r#"event_count{source="web",status="200"} 1"#)
+        {
+            metrics_ok = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(metrics_ok, "Metrics were never labelled by source");
+
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn rejected_lines_without_a_nats_input_never_stall_ingest() {
+    let dir =
+        std::env::temp_dir().join(format!("log-analyzer-dead-letters-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    // More rejected lines than the dead letter queue holds, then a good one.
+    let mut lines = "not a log line\n".repeat(1_500);
+    lines.push_str(r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#);
+    lines.push('\n');
+    let log = dir.join("access.log");
+    std::fs::write(&log, lines).unwrap();
+
+    // Start log-analyzer following the file
+    let metrics_port = portpicker::pick_unused_port().expect("No free ports available");
+    let metrics_url = format!("http://localhost:{metrics_port}");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-analyzer"))
+        .args([
+            "--input",
+            "file",
+            "--file",
+            &log.display().to_string(),
+            "--file-checkpoint",
+            &dir.join("offsets.json").display().to_string(),
+            "--dead-letter-subject",
+            "logs.rejected",
+            "--log-file",
+            &dir.join("server.log").display().to_string(),
+            "--port",
+            &metrics_port.to_string(),
+        ])
+        .spawn()
+        .expect("Failed to start log-analyzer");
+
+    // Poll /metrics until the good line has been counted
+    let client = reqwest::Client::new();
+    let mut metrics_ok = false;
+    for _ in 0..50 {
+        let resp = client
+            .get(format!("{metrics_url}/metrics"))
+            .send()
+            .await
+            .map(|r| r.text());
+        if let Ok(text) = resp
+            && text
+                .await
+                .unwrap_or_default()
+                .lines()
+                .any(|l| l.starts_with("event_count{") && l.contains(r#"status="200""#))
+        {
+            metrics_ok = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(metrics_ok, "Ingest stalled behind the rejected lines");
+
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
@@ -0,0 +1,104 @@
+#![cfg(feature = "kafka")]
+
+use rdkafka::{
+    ClientConfig, Offset, TopicPartitionList,
+    consumer::{BaseConsumer, Consumer},
+    producer::{FutureProducer, FutureRecord},
+};
+use std::time::Duration;
+use testcontainers::runners::AsyncRunner;
+use testcontainers_modules::kafka::apache::{KAFKA_PORT, Kafka};
+use tokio::{process::Command, time::sleep};
+
+#[ignore]
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn log_analyzer_consumes_kafka_and_commits_after_aggregation() {
+    // Start a single node Kafka container
+    let kafka_container = Kafka::default()
+        .start()
+        .await
+        .expect("Failed to start Kafka container");
+    let kafka_port = kafka_container
+        .get_host_port_ipv4(KAFKA_PORT)
+        .await
+        .expect("Failed to get Kafka port");
+    let brokers = format!("127.0.0.1:{kafka_port}");
+
+    // Publish a log to Kafka before the analyzer joins, it reads from earliest
+    let producer: FutureProducer = ClientConfig::new()
+        .set("bootstrap.servers", &brokers)
+        .create()
+        .expect("Failed to create Kafka producer");
+    let apache_log = r#"127.0.0.1 - - [25/Jul/2025:23:59:59 +0000] "GET /api HTTP/1.1" 200 512"#;
+    producer
+        .send(
+            FutureRecord::<(), _>::to("logs").payload(apache_log),
+            Duration::from_secs(10),
+        )
+        .await
+        .expect("Failed to produce log");
+
+    // Start log-analyzer
+    let metrics_port = portpicker::pick_unused_port().expect("No free ports available");
+    let metrics_url = format!("http://localhost:{metrics_port}");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_log-analyzer"))
+        .args([
+            "--input",
+            "kafka",
+            "--kafka-brokers",
+            &brokers,
+            "--kafka-group",
+            "it-group",
+            "--kafka-topics",
+            "logs",
+            "--port",
+            &metrics_port.to_string(),
+        ])
+        .spawn()
+        .expect("Failed to start log-analyzer");
+
+    // Poll /metrics for expected content
+    let client = reqwest::Client::new();
+    let mut metrics_ok = false;
+    for _ in 0..60 {
+        let resp = client
+            .get(format!("{metrics_url}/metrics"))
+            .send()
+            .await
+            .map(|r| r.text());
+        if let Ok(text) = resp
+            && text.await.unwrap_or_default().contains("event_count")
+        {
+            metrics_ok = true;
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert!(metrics_ok, "Metrics endpoint never returned event_count");
+
+    // The offset is committed once the record has been aggregated
+    let admin: BaseConsumer = ClientConfig::new()
+        .set("bootstrap.servers", &brokers)
+        .set("group.id", "it-group")
+        .create()
+        .expect("Failed to create Kafka consumer");
+    let mut partitions = TopicPartitionList::new();
+    partitions.add_partition("logs", 0);
+    let mut committed = Offset::Invalid;
+    for _ in 0..30 {
+        committed = admin
+            .committed_offsets(partitions.clone(), Duration::from_secs(5))
+            .expect("Failed to fetch committed offsets")
+            .find_partition("logs", 0)
+            .map(|p| p.offset())
+            .unwrap_or(Offset::Invalid);
+        if committed == Offset::Offset(1) {
+            break;
+        }
+        sleep(Duration::from_millis(500)).await;
+    }
+    assert_eq!(committed, Offset::Offset(1));
+
+    let _ = child.kill().await;
+    let _ = child.wait().await;
+}
@@ -1,15 +1,10 @@
 use std::fs::File;
 
-use args::CliArgs;
 use clap::Parser;
-use stream::run_log_stream;
+use noise_maker::{args::CliArgs, stream::run_log_stream};
 use tracing::info;
 use tracing_subscriber::{EnvFilter, fmt::writer::BoxMakeWriter};
 
-mod args;
-mod generator;
-mod stream;
-
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = CliArgs::parse();